@@ -1,65 +1,5691 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    fmt,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use axum::{
-    http::{header, HeaderValue, Request, StatusCode},
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, State},
+    http::{
+        header, request::Parts, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode,
+    },
     middleware::Next,
-    response::Response,
+    response::{IntoResponseParts, Response, ResponseParts},
+    routing::{get, post},
+    Json, Router,
 };
-use tower_http::cors::{Any, CorsLayer};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use pin_project_lite::pin_project;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tower::{util::Oneshot, Layer, Service, ServiceExt};
+use tower_http::cors::{
+    AllowHeaders, AllowMethods, AllowOrigin, AllowPrivateNetwork, Any, Cors, CorsLayer,
+    ExposeHeaders,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecurityConfigError {
+    #[error("invalid CORS origin `{origin}`: {reason}")]
+    InvalidOrigin {
+        origin: String,
+        reason: &'static str,
+    },
+    #[error("invalid CORS origin pattern `{pattern}`: {source}")]
+    InvalidOriginPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    #[error("invalid HTTP method `{0}`")]
+    InvalidMethod(String),
+    #[error("invalid header name `{0}`")]
+    InvalidHeaderName(String),
+    #[error("invalid value `{value}` for header {header}")]
+    InvalidHeaderValue { header: &'static str, value: String },
+    #[error("invalid Content-Security-Policy token `{token}`: {reason}")]
+    InvalidCsp { token: String, reason: &'static str },
+    #[error("invalid Permissions-Policy token `{token}`: {reason}")]
+    InvalidPermissionsPolicy { token: String, reason: &'static str },
+    #[error("invalid value `{value}` for environment variable {var}")]
+    InvalidEnvVar { var: &'static str, value: String },
+    #[error("failed to read security config {path}: {source}")]
+    ReadConfig {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("failed to open report file {path}: {source}")]
+    ReportSink {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("invalid reporting setting `{token}`: {reason}")]
+    InvalidReporting { token: String, reason: &'static str },
+    #[error("failed to parse security config: {0}")]
+    ParseConfig(#[from] toml::de::Error),
+    #[error("insecure CORS policy: {}", join_issues(.0))]
+    InsecureCors(Vec<CorsPolicyIssue>),
+    #[error("no CORS origins configured; set ALLOWED_ORIGINS or `allowed_origins` (`*` for a public API)")]
+    NoAllowedOrigins,
+    #[error("unknown CORS profile `{0}`")]
+    UnknownCorsProfile(String),
+    #[error("CORS profile `{profile}`: {source}")]
+    CorsProfile {
+        profile: String,
+        source: Box<SecurityConfigError>,
+    },
+    #[error("invalid HTTPS redirect host `{host}`: {reason}")]
+    InvalidRedirectHost { host: String, reason: &'static str },
+    #[error("HTTPS redirect needs at least one allowed host, otherwise any Host header would be redirected to")]
+    NoRedirectHosts,
+    #[error("security header profile `{profile}`: {source}")]
+    HeaderProfile {
+        profile: String,
+        source: Box<SecurityConfigError>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorsPolicyIssue {
+    WildcardOriginWithCredentials,
+    WildcardMethodsWithCredentials,
+    WildcardHeadersWithCredentials,
+    WildcardExposeWithCredentials,
+    WildcardMixedWithOrigins,
+    NullOrigin,
+    TopLevelSubdomainWildcard,
+    WildcardPrivateNetwork,
+    PermissiveOriginPatternWithCredentials,
+}
+
+impl fmt::Display for CorsPolicyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::WildcardOriginWithCredentials => {
+                "`*` origins with allow_credentials would let any site make \
+                 authenticated requests; list the trusted origins instead"
+            }
+            Self::WildcardMethodsWithCredentials => {
+                "`*` methods cannot be combined with allow_credentials; list the methods explicitly"
+            }
+            Self::WildcardHeadersWithCredentials => {
+                "`*` headers cannot be combined with allow_credentials; list the headers explicitly"
+            }
+            Self::WildcardExposeWithCredentials => {
+                "`*` expose_headers cannot be combined with allow_credentials; \
+                 list the exposed headers explicitly"
+            }
+            Self::WildcardMixedWithOrigins => {
+                "`*` already allows every origin; remove it or drop the explicit origins"
+            }
+            Self::NullOrigin => {
+                "the `null` origin is sent by sandboxed iframes and file:// pages, \
+                 so any attacker can forge it"
+            }
+            Self::TopLevelSubdomainWildcard => {
+                "subdomain wildcards must sit below a registrable domain, \
+                 e.g. `https://*.app.example.com` rather than `https://*.com`"
+            }
+            Self::WildcardPrivateNetwork => {
+                "`*` private_network_origins would let any public site reach private \
+                 network devices; list the origins that need access"
+            }
+            Self::PermissiveOriginPatternWithCredentials => {
+                "a `regex:` origin matches hosts outside the domain it names (e.g. \
+                 `.*example\\.com` also matches `evilexample.com`), which with \
+                 allow_credentials would let those sites make authenticated requests; \
+                 escape the dots and end the pattern in `\\.example\\.com`"
+            }
+        })
+    }
+}
+
+fn join_issues(issues: &[CorsPolicyIssue]) -> String {
+    issues
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// CORS policy loaded from the environment or from the `[cors]` table of a TOML file.
+///
+/// Origins are exact (`https://app.example.com`), subdomain wildcards
+/// (`https://*.app.example.com`) or anchored regular expressions
+/// (`regex:https://tenant-[a-z0-9]+\.example\.com`). With credentials, a pattern
+/// that also matches look-alike hosts such as `evilexample.com` is rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_secs: Option<u64>,
+    /// Response headers browser scripts may read, besides the CORS-safelisted ones.
+    pub expose_headers: Vec<String>,
+    /// Expose the `RateLimit-*`, `X-RateLimit-*` and `Retry-After` headers set by a
+    /// rate-limiting layer such as tower-governor.
+    pub rate_limit_headers: bool,
+    /// Expose the header set by `SetRequestIdLayer`/`PropagateRequestIdLayer`.
+    pub request_id_header: Option<String>,
+    /// Origins allowed to reach this server from a less private network (Chrome's
+    /// Private Network Access). Preflights from these origins that carry
+    /// `Access-Control-Request-Private-Network` get
+    /// `Access-Control-Allow-Private-Network: true`. Empty disables it.
+    pub private_network_origins: Vec<String>,
+}
+
+const RATE_LIMIT_HEADERS: [&str; 8] = [
+    "ratelimit-limit",
+    "ratelimit-remaining",
+    "ratelimit-reset",
+    "ratelimit-policy",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+    "retry-after",
+];
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].map(String::from).to_vec(),
+            allowed_headers: ["content-type", "authorization"].map(String::from).to_vec(),
+            allow_credentials: true,
+            max_age_secs: None,
+            expose_headers: Vec::new(),
+            rate_limit_headers: false,
+            request_id_header: None,
+            private_network_origins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SecurityConfigFile {
+    #[serde(default)]
+    cors: CorsConfig,
+    #[serde(default)]
+    security_headers: SecurityHeadersConfig,
+    #[serde(default)]
+    security_header_profiles: BTreeMap<String, SecurityHeaderProfileConfig>,
+    #[serde(default)]
+    https_redirect: HttpsRedirectConfig,
+}
+
+fn read_config_file(path: &Path) -> Result<SecurityConfigFile, SecurityConfigError> {
+    Ok(toml::from_str(&read_config_text(path)?)?)
+}
+
+fn read_config_text(path: &Path) -> Result<String, SecurityConfigError> {
+    std::fs::read_to_string(path).map_err(|source| SecurityConfigError::ReadConfig {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+impl CorsConfig {
+    /// Reads `ALLOWED_ORIGINS`, `ALLOWED_METHODS`, `ALLOWED_HEADERS`,
+    /// `CORS_EXPOSE_HEADERS` (comma-separated), `CORS_ALLOW_CREDENTIALS`,
+    /// `CORS_MAX_AGE`, `CORS_EXPOSE_RATE_LIMIT_HEADERS` (`true`/`false`) and
+    /// `CORS_EXPOSE_REQUEST_ID_HEADER` (a header name such as `x-request-id`).
+    /// Unset variables keep their defaults, except that `ALLOWED_ORIGINS` is
+    /// required.
+    pub fn from_env() -> Result<Self, SecurityConfigError> {
+        let mut config = Self::default();
+
+        if let Some(origins) = env_list("ALLOWED_ORIGINS") {
+            config.allowed_origins = origins;
+        }
+        if let Some(methods) = env_list("ALLOWED_METHODS") {
+            config.allowed_methods = methods;
+        }
+        if let Some(headers) = env_list("ALLOWED_HEADERS") {
+            config.allowed_headers = headers;
+        }
+        if let Some(headers) = env_list("CORS_EXPOSE_HEADERS") {
+            config.expose_headers = headers;
+        }
+        if let Some(allow) = env_parse("CORS_ALLOW_CREDENTIALS")? {
+            config.allow_credentials = allow;
+        }
+        if let Some(secs) = env_parse("CORS_MAX_AGE")? {
+            config.max_age_secs = Some(secs);
+        }
+        if let Some(expose) = env_parse("CORS_EXPOSE_RATE_LIMIT_HEADERS")? {
+            config.rate_limit_headers = expose;
+        }
+        if let Ok(name) = std::env::var("CORS_EXPOSE_REQUEST_ID_HEADER") {
+            let name = name.trim();
+            config.request_id_header = (!name.is_empty()).then(|| name.to_string());
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, SecurityConfigError> {
+        let file = read_config_file(path.as_ref())?;
+        file.cors.validate()?;
+        Ok(file.cors)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, SecurityConfigError> {
+        let file: SecurityConfigFile = toml::from_str(contents)?;
+        file.cors.validate()?;
+        Ok(file.cors)
+    }
+
+    /// Checks every configured value so mistakes surface at startup, and rejects
+    /// combinations that would open the API to arbitrary sites.
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        // Fail rather than fall back to a development origin in production.
+        if self.allowed_origins.is_empty() {
+            return Err(SecurityConfigError::NoAllowedOrigins);
+        }
+        let issues = self.policy_issues();
+        if !issues.is_empty() {
+            return Err(SecurityConfigError::InsecureCors(issues));
+        }
+
+        self.origins()?;
+        self.methods()?;
+        self.headers()?;
+        self.exposed_headers()?;
+        self.private_network_matchers()?;
+        Ok(())
+    }
+
+    pub fn with_rate_limit_headers(mut self) -> Self {
+        self.rate_limit_headers = true;
+        self
+    }
+
+    pub fn with_request_id_header(mut self, name: impl Into<String>) -> Self {
+        self.request_id_header = Some(name.into());
+        self
+    }
+
+    pub fn policy_issues(&self) -> Vec<CorsPolicyIssue> {
+        let mut issues = Vec::new();
+        let any_origin = is_wildcard(&self.allowed_origins);
+        // Invalid patterns are reported by `origins()`; only check the ones that compile.
+        let patterns: Vec<(&str, Regex)> = self
+            .allowed_origins
+            .iter()
+            .filter_map(|origin| origin.trim().strip_prefix("regex:"))
+            .filter_map(|pattern| {
+                let regex = Regex::new(&format!("^(?:{pattern})$")).ok()?;
+                Some((pattern, regex))
+            })
+            .collect();
+
+        if any_origin && self.allow_credentials {
+            issues.push(CorsPolicyIssue::WildcardOriginWithCredentials);
+        }
+        if is_wildcard(&self.allowed_methods) && self.allow_credentials {
+            issues.push(CorsPolicyIssue::WildcardMethodsWithCredentials);
+        }
+        if is_wildcard(&self.allowed_headers) && self.allow_credentials {
+            issues.push(CorsPolicyIssue::WildcardHeadersWithCredentials);
+        }
+        if is_wildcard(&self.expose_headers) && self.allow_credentials {
+            issues.push(CorsPolicyIssue::WildcardExposeWithCredentials);
+        }
+        if any_origin && self.allowed_origins.len() > 1 {
+            issues.push(CorsPolicyIssue::WildcardMixedWithOrigins);
+        }
+        if self
+            .allowed_origins
+            .iter()
+            .any(|origin| origin.trim().eq_ignore_ascii_case("null"))
+            || patterns.iter().any(|(_, regex)| regex.is_match("null"))
+        {
+            issues.push(CorsPolicyIssue::NullOrigin);
+        }
+        if self.allow_credentials
+            && patterns.iter().any(|(pattern, regex)| {
+                pattern_probes(pattern)
+                    .iter()
+                    .any(|origin| regex.is_match(origin))
+            })
+        {
+            issues.push(CorsPolicyIssue::PermissiveOriginPatternWithCredentials);
+        }
+        if self.allowed_origins.iter().any(|origin| {
+            subdomain_wildcard_suffix(origin.trim())
+                .is_some_and(|suffix| suffix.trim_start_matches('.').split('.').count() < 2)
+        }) {
+            issues.push(CorsPolicyIssue::TopLevelSubdomainWildcard);
+        }
+        if is_wildcard(&self.private_network_origins) {
+            issues.push(CorsPolicyIssue::WildcardPrivateNetwork);
+        }
+
+        issues
+    }
+
+    pub fn into_layer(&self) -> Result<CorsLayer, SecurityConfigError> {
+        self.validate()?;
+
+        let allow_origin = if is_wildcard(&self.allowed_origins) {
+            AllowOrigin::from(Any)
+        } else {
+            origin_matchers_to_allow_origin(self.origins()?)
+        };
+        let allow_methods = if is_wildcard(&self.allowed_methods) {
+            AllowMethods::from(Any)
+        } else {
+            AllowMethods::list(self.methods()?)
+        };
+        let allow_headers = if is_wildcard(&self.allowed_headers) {
+            AllowHeaders::from(Any)
+        } else {
+            AllowHeaders::list(self.headers()?)
+        };
+        let expose_headers = if is_wildcard(&self.expose_headers) {
+            ExposeHeaders::from(Any)
+        } else {
+            ExposeHeaders::list(self.exposed_headers()?)
+        };
+
+        let mut layer = CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(allow_methods)
+            .allow_headers(allow_headers)
+            .expose_headers(expose_headers)
+            .allow_credentials(self.allow_credentials)
+            .vary([
+                header::ORIGIN,
+                header::ACCESS_CONTROL_REQUEST_METHOD,
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+            ]);
+
+        let private_network = self.private_network_matchers()?;
+        if !private_network.is_empty() {
+            let private_network = Arc::new(private_network);
+            layer = layer
+                .allow_private_network(AllowPrivateNetwork::predicate(
+                    move |origin: &HeaderValue, _: &Parts| {
+                        private_network
+                            .iter()
+                            .any(|matcher| matcher.matches(origin))
+                    },
+                ))
+                .vary([
+                    header::ORIGIN,
+                    header::ACCESS_CONTROL_REQUEST_METHOD,
+                    header::ACCESS_CONTROL_REQUEST_HEADERS,
+                    HeaderName::from_static("access-control-request-private-network"),
+                ]);
+        }
+
+        if let Some(secs) = self.max_age_secs {
+            layer = layer.max_age(Duration::from_secs(secs));
+        }
+
+        Ok(layer)
+    }
+
+    fn origins(&self) -> Result<Vec<OriginMatcher>, SecurityConfigError> {
+        self.allowed_origins
+            .iter()
+            .filter(|origin| origin.trim() != "*")
+            .map(|origin| OriginMatcher::parse(origin))
+            .collect()
+    }
+
+    fn methods(&self) -> Result<Vec<Method>, SecurityConfigError> {
+        self.allowed_methods
+            .iter()
+            .filter(|method| method.trim() != "*")
+            .map(|method| {
+                Method::from_bytes(method.trim().to_ascii_uppercase().as_bytes())
+                    .map_err(|_| SecurityConfigError::InvalidMethod(method.clone()))
+            })
+            .collect()
+    }
+
+    fn private_network_matchers(&self) -> Result<Vec<OriginMatcher>, SecurityConfigError> {
+        self.private_network_origins
+            .iter()
+            .filter(|origin| origin.trim() != "*")
+            .map(|origin| OriginMatcher::parse(origin))
+            .collect()
+    }
+
+    fn headers(&self) -> Result<Vec<HeaderName>, SecurityConfigError> {
+        parse_header_names(&self.allowed_headers)
+    }
+
+    /// Explicit `expose_headers` plus the headers of the enabled security layers,
+    /// without duplicates.
+    fn exposed_headers(&self) -> Result<Vec<HeaderName>, SecurityConfigError> {
+        let mut names = parse_header_names(&self.expose_headers)?;
+        if self.rate_limit_headers {
+            names.extend(RATE_LIMIT_HEADERS.map(HeaderName::from_static));
+        }
+        if let Some(request_id) = &self.request_id_header {
+            names.extend(parse_header_names(std::slice::from_ref(request_id))?);
+        }
+
+        let mut seen = HashSet::new();
+        names.retain(|name| seen.insert(name.clone()));
+        Ok(names)
+    }
+}
+
+fn parse_header_names(names: &[String]) -> Result<Vec<HeaderName>, SecurityConfigError> {
+    names
+        .iter()
+        .filter(|name| name.trim() != "*")
+        .map(|name| {
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| SecurityConfigError::InvalidHeaderName(name.clone()))
+        })
+        .collect()
+}
+
+/// Origins no allowlist should match, used to catch `regex:` patterns such as
+/// `.*` or `https?://.*` that accept any host.
+const SENTINEL_ORIGINS: [&str; 3] = [
+    "https://attacker.invalid",
+    "http://attacker.invalid",
+    "https://attacker.invalid:8443",
+];
+
+/// Origins a `regex:` pattern must reject: the sentinels plus look-alikes of the
+/// host the pattern names, i.e. `https://evilexample.com`,
+/// `https://example.com.attacker.invalid` and the host with each dot replaced
+/// by a letter, which an unescaped `.` accepts.
+fn pattern_probes(pattern: &str) -> Vec<String> {
+    let mut probes: Vec<String> = SENTINEL_ORIGINS
+        .iter()
+        .map(|&origin| origin.into())
+        .collect();
+    let Some(host) = pattern_host(pattern) else {
+        return probes;
+    };
+
+    probes.push(format!("https://evil{host}"));
+    probes.push(format!("https://{host}.attacker.invalid"));
+    for (index, _) in host.match_indices('.') {
+        let mut lookalike = host.clone();
+        lookalike.replace_range(index..=index, "x");
+        probes.push(format!("https://{lookalike}"));
+    }
+    probes
+}
+
+/// Longest literal run of host characters in a pattern, with `\.` unescaped,
+/// e.g. `example.com` for `https://[a-z]+\.example\.com`.
+fn pattern_host(pattern: &str) -> Option<String> {
+    let mut runs = Vec::new();
+    let mut run = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '\\' => chars.next().filter(|&next| next == '.'),
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '.' => Some(c),
+            _ => None,
+        };
+        match literal {
+            Some(c) => run.push(c),
+            None => runs.push(std::mem::take(&mut run)),
+        }
+    }
+    runs.push(run);
+
+    runs.into_iter()
+        .map(|run| run.trim_matches(['.', '-']).to_string())
+        .filter(|run| run.contains('.'))
+        .max_by_key(String::len)
+}
+
+fn is_wildcard(values: &[String]) -> bool {
+    values.iter().any(|value| value.trim() == "*")
+}
+
+fn env_parse<T: FromStr>(var: &'static str) -> Result<Option<T>, SecurityConfigError> {
+    let Ok(value) = std::env::var(var) else {
+        return Ok(None);
+    };
+    value
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|_| SecurityConfigError::InvalidEnvVar { var, value })
+}
+
+fn env_list(var: &str) -> Option<Vec<String>> {
+    let value = std::env::var(var).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+fn parse_origin(origin: &str) -> Result<HeaderValue, SecurityConfigError> {
+    let invalid = |reason| SecurityConfigError::InvalidOrigin {
+        origin: origin.to_string(),
+        reason,
+    };
+
+    let origin = origin.trim().trim_end_matches('/');
+    let (scheme, host) = origin
+        .split_once("://")
+        .ok_or_else(|| invalid("expected scheme://host[:port]"))?;
+    if scheme != "http" && scheme != "https" {
+        return Err(invalid("scheme must be http or https"));
+    }
+    if host.is_empty() || host.contains(['/', '?', '#']) {
+        return Err(invalid("origin must not contain a path, query or fragment"));
+    }
+
+    HeaderValue::from_str(origin).map_err(|_| invalid("not a valid header value"))
+}
+
+#[derive(Debug, Clone)]
+enum OriginMatcher {
+    Exact(HeaderValue),
+    Subdomain {
+        scheme: String,
+        suffix: String,
+        port: Option<String>,
+    },
+    Pattern(Regex),
+}
+
+impl OriginMatcher {
+    fn parse(origin: &str) -> Result<Self, SecurityConfigError> {
+        let origin = origin.trim();
+
+        if let Some(pattern) = origin.strip_prefix("regex:") {
+            return Regex::new(&format!("^(?:{pattern})$"))
+                .map(Self::Pattern)
+                .map_err(|source| SecurityConfigError::InvalidOriginPattern {
+                    pattern: pattern.to_string(),
+                    source,
+                });
+        }
+
+        if let Some(suffix) = subdomain_wildcard_suffix(origin) {
+            // Validate the rest of the origin by substituting a concrete label.
+            let sample = origin.replacen("*.", "wildcard.", 1);
+            parse_origin(&sample).map_err(|_| SecurityConfigError::InvalidOrigin {
+                origin: origin.to_string(),
+                reason: "expected scheme://*.domain[:port]",
+            })?;
+
+            let (scheme, _) = origin.split_once("://").unwrap_or_default();
+            let (suffix, port) = match suffix.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port.to_string())),
+                None => (suffix, None),
+            };
+            return Ok(Self::Subdomain {
+                scheme: scheme.to_ascii_lowercase(),
+                suffix: suffix.to_ascii_lowercase(),
+                port,
+            });
+        }
+
+        parse_origin(origin).map(Self::Exact)
+    }
+
+    fn matches(&self, origin: &HeaderValue) -> bool {
+        match self {
+            Self::Exact(allowed) => allowed == origin,
+            Self::Pattern(regex) => origin.to_str().is_ok_and(|origin| regex.is_match(origin)),
+            Self::Subdomain {
+                scheme,
+                suffix,
+                port,
+            } => {
+                let Some((request_scheme, authority)) = origin
+                    .to_str()
+                    .ok()
+                    .and_then(|origin| origin.split_once("://"))
+                else {
+                    return false;
+                };
+                let (host, request_port) = match authority.rsplit_once(':') {
+                    Some((host, port)) => (host, Some(port)),
+                    None => (authority, None),
+                };
+                let host = host.to_ascii_lowercase();
+
+                // `suffix` starts with a dot, so `evilapp.example.com` never matches
+                // `*.app.example.com`, and the label before it must be non-empty.
+                request_scheme.eq_ignore_ascii_case(scheme)
+                    && request_port == port.as_deref()
+                    && host.len() > suffix.len()
+                    && host.ends_with(suffix.as_str())
+                    && !host.contains(['/', '?', '#', '@'])
+            }
+        }
+    }
+}
+
+fn subdomain_wildcard_suffix(origin: &str) -> Option<&str> {
+    let (_, authority) = origin.split_once("://")?;
+    authority
+        .strip_prefix('*')
+        .filter(|rest| rest.starts_with('.'))
+}
+
+fn origin_matchers_to_allow_origin(matchers: Vec<OriginMatcher>) -> AllowOrigin {
+    if matchers
+        .iter()
+        .all(|matcher| matches!(matcher, OriginMatcher::Exact(_)))
+    {
+        return AllowOrigin::list(matchers.into_iter().filter_map(|matcher| match matcher {
+            OriginMatcher::Exact(origin) => Some(origin),
+            _ => None,
+        }));
+    }
+
+    // A predicate echoes the matched request origin back, so `Vary: Origin` is required.
+    let matchers = Arc::new(matchers);
+    AllowOrigin::predicate(move |origin: &HeaderValue, _: &Parts| {
+        matchers.iter().any(|matcher| matcher.matches(origin))
+    })
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CorsProfileConfig {
+    /// Route prefixes the profile applies to in [`CorsProfiles::into_layer`].
+    #[serde(default)]
+    pub routes: Vec<String>,
+    #[serde(flatten)]
+    pub cors: CorsConfig,
+}
+
+/// Named CORS policies from `[cors_profiles.<name>]` tables, with `[cors]` as the
+/// fallback for routes that have no profile attached. Fields a profile leaves out
+/// take the fallback's values; with [`with_profile`](Self::with_profile), start
+/// from `..profiles.fallback.clone()` for the same effect.
+///
+/// ```toml
+/// [cors]
+/// allowed_origins = ["https://app.example.com"]
+///
+/// [cors_profiles.public]
+/// routes = ["/api/public"]
+/// allowed_origins = ["*"]
+/// allowed_methods = ["GET"]
+/// allow_credentials = false
+/// ```
+///
+/// Apply them with [`into_layer`](Self::into_layer), which picks one policy per
+/// request by path:
+///
+/// ```ignore
+/// let app = Router::new()
+///     .nest("/api/public", public_routes)
+///     .nest("/api", private_routes)
+///     .layer(profiles.into_layer()?);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CorsProfiles {
+    pub fallback: CorsConfig,
+    pub profiles: BTreeMap<String, CorsProfileConfig>,
+}
+
+impl CorsProfiles {
+    pub fn new(fallback: CorsConfig) -> Self {
+        Self {
+            fallback,
+            profiles: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_profile(
+        mut self,
+        name: impl Into<String>,
+        routes: impl IntoIterator<Item = impl Into<String>>,
+        cors: CorsConfig,
+    ) -> Self {
+        let routes = routes.into_iter().map(Into::into).collect();
+        self.profiles
+            .insert(name.into(), CorsProfileConfig { routes, cors });
+        self
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, SecurityConfigError> {
+        Self::from_toml_str(&read_config_text(path.as_ref())?)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, SecurityConfigError> {
+        let mut file: toml::Table = toml::from_str(contents)?;
+        let table = |value: Option<toml::Value>| -> Result<toml::Table, SecurityConfigError> {
+            Ok(value
+                .map(toml::Value::try_into)
+                .transpose()?
+                .unwrap_or_default())
+        };
+        let fallback = table(file.remove("cors"))?;
+
+        let mut profiles = BTreeMap::new();
+        for (name, profile) in table(file.remove("cors_profiles"))? {
+            // Start from `[cors]` so a profile only lists what it changes.
+            let mut merged = fallback.clone();
+            merged.extend(table(Some(profile))?);
+            profiles.insert(name, toml::Value::Table(merged).try_into()?);
+        }
+
+        let profiles = Self {
+            fallback: toml::Value::Table(fallback).try_into()?,
+            profiles,
+        };
+        profiles.validate()?;
+        profiles.warn_overlaps();
+        Ok(profiles)
+    }
+
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        self.fallback.validate()?;
+        for (name, profile) in &self.profiles {
+            profile
+                .cors
+                .validate()
+                .map_err(|source| SecurityConfigError::CorsProfile {
+                    profile: name.clone(),
+                    source: Box::new(source),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Builds the layer for a named profile, to attach with `Router::layer`.
+    /// Never wrap such a router in [`fallback_layer`](Self::fallback_layer) as
+    /// well: the outer layer answers preflights before the profile sees them.
+    pub fn layer(&self, name: &str) -> Result<CorsLayer, SecurityConfigError> {
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| SecurityConfigError::UnknownCorsProfile(name.to_string()))?;
+        profile
+            .cors
+            .into_layer()
+            .map_err(|source| SecurityConfigError::CorsProfile {
+                profile: name.to_string(),
+                source: Box::new(source),
+            })
+    }
+
+    /// Layer for routes without a profile. Only attach it to routers that have
+    /// no profile layer inside; see [`layer`](Self::layer).
+    pub fn fallback_layer(&self) -> Result<CorsLayer, SecurityConfigError> {
+        self.fallback.into_layer()
+    }
+
+    /// One layer for the whole router that applies the profile whose longest
+    /// route prefix matches the request path, or the fallback.
+    pub fn into_layer(&self) -> Result<CorsProfilesLayer, SecurityConfigError> {
+        let mut layers = self
+            .profiles
+            .keys()
+            .map(|name| self.layer(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        layers.push(self.fallback_layer()?);
+
+        Ok(CorsProfilesLayer {
+            routes: Arc::new(self.indexed_routes()),
+            layers: Arc::new(layers),
+        })
+    }
+
+    /// Every route prefix with the index of its profile in `profiles`, longest
+    /// first, so the first prefix containing a path is the one that applies.
+    fn indexed_routes(&self) -> Vec<(String, usize)> {
+        let mut routes: Vec<_> = self
+            .profiles
+            .values()
+            .enumerate()
+            .flat_map(|(index, profile)| {
+                profile
+                    .routes
+                    .iter()
+                    .map(move |route| (route.clone(), index))
+            })
+            .collect();
+        routes.sort_by_key(|(route, _)| std::cmp::Reverse(route_rank(route)));
+        routes
+    }
+
+    /// Returns the profile whose longest route prefix matches `path`, or the fallback.
+    pub fn for_path(&self, path: &str) -> &CorsConfig {
+        self.profile_for_path(path)
+            .map_or(&self.fallback, |profile| &profile.cors)
+    }
+
+    fn profile_for_path(&self, path: &str) -> Option<&CorsProfileConfig> {
+        longest_route(
+            self.profiles.values().flat_map(|profile| {
+                profile
+                    .routes
+                    .iter()
+                    .map(move |route| (route.as_str(), profile))
+            }),
+            path,
+        )
+    }
+
+    /// Pairs of profiles whose route prefixes cover the same paths, as
+    /// `(profile, route, other profile, other route)`.
+    pub fn overlaps(&self) -> Vec<(&str, &str, &str, &str)> {
+        let routes: Vec<(&str, &str)> = self
+            .profiles
+            .iter()
+            .flat_map(|(name, profile)| {
+                profile
+                    .routes
+                    .iter()
+                    .map(move |route| (name.as_str(), route.as_str()))
+            })
+            .collect();
+
+        let mut overlaps = Vec::new();
+        for (i, &(name, route)) in routes.iter().enumerate() {
+            for &(other_name, other_route) in &routes[i + 1..] {
+                if name != other_name
+                    && (route_contains(route, other_route) || route_contains(other_route, route))
+                {
+                    overlaps.push((name, route, other_name, other_route));
+                }
+            }
+        }
+        overlaps
+    }
+
+    pub fn warn_overlaps(&self) {
+        for (profile, route, other_profile, other_route) in self.overlaps() {
+            tracing::warn!(
+                profile,
+                route,
+                other_profile,
+                other_route,
+                "CORS profiles overlap; the longest matching route wins"
+            );
+        }
+    }
+}
+
+/// Layer built by [`CorsProfiles::into_layer`]. Wraps the inner service once per
+/// profile and forwards each request to the one its path selects.
+#[derive(Debug, Clone)]
+pub struct CorsProfilesLayer {
+    /// Route prefixes with the index of their layer, longest first.
+    routes: Arc<Vec<(String, usize)>>,
+    /// One per profile, then the fallback.
+    layers: Arc<Vec<CorsLayer>>,
+}
+
+impl<S: Clone> Layer<S> for CorsProfilesLayer {
+    type Service = CorsProfilesService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsProfilesService {
+            routes: Arc::clone(&self.routes),
+            services: self
+                .layers
+                .iter()
+                .map(|layer| layer.layer(inner.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsProfilesService<S> {
+    routes: Arc<Vec<(String, usize)>>,
+    services: Vec<Cors<S>>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for CorsProfilesService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Oneshot<Cors<S>, Request<ReqBody>>;
+
+    // Which wrapped service handles the request depends on its path, so each
+    // call readies its own clone instead.
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let index = route_index(&self.routes, req.uri().path()).unwrap_or(self.services.len() - 1);
+        self.services[index].clone().oneshot(req)
+    }
+}
+
+/// Index of the first, i.e. longest, route in `routes` that contains `path`.
+fn route_index(routes: &[(String, usize)], path: &str) -> Option<usize> {
+    routes
+        .iter()
+        .find(|(route, _)| route_contains(route, path))
+        .map(|(_, index)| *index)
+}
+
+/// The value paired with the longest route that contains `path`, the first
+/// one on a tie. Every route-prefix lookup goes through this or [`route_rank`],
+/// so CORS and security header profiles pick the same route.
+fn longest_route<'a, T>(routes: impl IntoIterator<Item = (&'a str, T)>, path: &str) -> Option<T> {
+    routes
+        .into_iter()
+        .filter(|(route, _)| route_contains(route, path))
+        .min_by_key(|(route, _)| std::cmp::Reverse(route_rank(route)))
+        .map(|(_, value)| value)
+}
+
+/// Length used to rank route prefixes; `/api/` and `/api` cover the same paths
+/// and rank the same.
+fn route_rank(route: &str) -> usize {
+    route.trim_end_matches('/').len()
+}
+
+fn route_contains(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Origin allowlist that can be swapped at runtime without rebuilding the router.
+///
+/// Only `allowed_origins` is reloaded; other `[cors]` settings are baked into the
+/// layer and need a restart. Requests already past the origin check keep the
+/// snapshot they were admitted with.
+#[derive(Clone)]
+pub struct ReloadableOrigins {
+    path: PathBuf,
+    config: CorsConfig,
+    current: Arc<RwLock<Arc<OriginAllowlist>>>,
+}
+
+struct OriginAllowlist {
+    origins: Vec<String>,
+    any: bool,
+    matchers: Vec<OriginMatcher>,
+}
+
+impl OriginAllowlist {
+    fn new(config: &CorsConfig) -> Result<Self, SecurityConfigError> {
+        Ok(Self {
+            origins: config.allowed_origins.clone(),
+            any: is_wildcard(&config.allowed_origins),
+            matchers: config.origins()?,
+        })
+    }
+
+    fn allows(&self, origin: &HeaderValue) -> bool {
+        self.any || self.matchers.iter().any(|matcher| matcher.matches(origin))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OriginDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl OriginDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl ReloadableOrigins {
+    pub fn from_toml_file(path: impl Into<PathBuf>) -> Result<Self, SecurityConfigError> {
+        let path = path.into();
+        let config = CorsConfig::from_toml_file(&path)?;
+        let allowlist = OriginAllowlist::new(&config)?;
+        Ok(Self {
+            path,
+            config,
+            current: Arc::new(RwLock::new(Arc::new(allowlist))),
+        })
+    }
+
+    /// Builds a CORS layer whose origin check always reads the latest allowlist.
+    pub fn layer(&self) -> Result<CorsLayer, SecurityConfigError> {
+        let current = Arc::clone(&self.current);
+        let layer = self
+            .config
+            .into_layer()?
+            .allow_origin(AllowOrigin::predicate(
+                move |origin: &HeaderValue, _: &Parts| {
+                    let allowlist = Arc::clone(&current.read().unwrap_or_else(|e| e.into_inner()));
+                    allowlist.allows(origin)
+                },
+            ));
+        Ok(layer)
+    }
+
+    pub fn origins(&self) -> Vec<String> {
+        self.snapshot().origins.clone()
+    }
+
+    /// Re-reads the file and swaps in the new allowlist. On error the current
+    /// allowlist stays in place.
+    pub fn reload(&self) -> Result<OriginDiff, SecurityConfigError> {
+        let reloaded = CorsConfig::from_toml_file(&self.path)?;
+
+        // Check the new origins against the policy the layer was built with.
+        let config = CorsConfig {
+            allowed_origins: reloaded.allowed_origins.clone(),
+            ..self.config.clone()
+        };
+        if config != reloaded {
+            tracing::warn!(
+                path = %self.path.display(),
+                "only allowed_origins is reloaded; restart to apply other CORS changes"
+            );
+        }
+        config.validate()?;
+
+        let next = Arc::new(OriginAllowlist::new(&config)?);
+        let previous = {
+            let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+            std::mem::replace(&mut *current, Arc::clone(&next))
+        };
+
+        let old: BTreeSet<_> = previous.origins.iter().collect();
+        let new: BTreeSet<_> = next.origins.iter().collect();
+        let diff = OriginDiff {
+            added: new.difference(&old).map(|o| o.to_string()).collect(),
+            removed: old.difference(&new).map(|o| o.to_string()).collect(),
+        };
+        if diff.is_empty() {
+            tracing::info!(path = %self.path.display(), "CORS allowlist reloaded, no changes");
+        } else {
+            tracing::info!(
+                path = %self.path.display(),
+                added = ?diff.added,
+                removed = ?diff.removed,
+                "CORS allowlist reloaded"
+            );
+        }
+        Ok(diff)
+    }
+
+    /// Reloads whenever the process receives SIGHUP.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(&self) -> std::io::Result<tokio::task::JoinHandle<()>> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let origins = self.clone();
+        Ok(tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                origins.reload_logging_errors();
+            }
+        }))
+    }
+
+    /// Polls the file's modification time and reloads when it changes.
+    pub fn watch_file(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let origins = self.clone();
+        tokio::spawn(async move {
+            let mut last_modified = modified_at(&origins.path);
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let modified = modified_at(&origins.path);
+                if modified.is_some() && modified != last_modified {
+                    last_modified = modified;
+                    origins.reload_logging_errors();
+                }
+            }
+        })
+    }
+
+    fn reload_logging_errors(&self) {
+        if let Err(error) = self.reload() {
+            tracing::error!(
+                path = %self.path.display(),
+                %error,
+                "CORS allowlist reload failed; keeping the previous list"
+            );
+        }
+    }
+
+    fn snapshot(&self) -> Arc<OriginAllowlist> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Logs and counts cross-origin requests that the CORS policy will refuse, since
+/// the tower-http layer drops them silently.
+///
+/// Install it outside the CORS layer so it also sees preflights, and build it
+/// from the same policy as that layer:
+///
+/// ```ignore
+/// let monitor = CorsMonitor::new(&config)?;
+/// let app = router
+///     .layer(config.into_layer()?)
+///     .layer(middleware::from_fn_with_state(monitor.clone(), log_cors_rejections));
+/// ```
+///
+/// Use [`for_reloadable`](Self::for_reloadable) with [`ReloadableOrigins`] and
+/// [`for_profiles`](Self::for_profiles) with [`CorsProfiles::into_layer`].
+#[derive(Clone)]
+pub struct CorsMonitor {
+    inner: Arc<CorsMonitorInner>,
+}
+
+struct CorsMonitorInner {
+    /// Route prefixes with the index of their policy, longest first.
+    routes: Vec<(String, usize)>,
+    /// One per profile, then the fallback.
+    policies: Vec<MonitoredPolicy>,
+    sample_every: AtomicU64,
+    rejected_origins: AtomicU64,
+    rejected_methods: AtomicU64,
+    rejected_headers: AtomicU64,
+    unknown_origins: Mutex<HashMap<String, u64>>,
+}
+
+/// Distinct unknown origins tracked before new ones are only counted in aggregate.
+const MAX_TRACKED_ORIGINS: usize = 256;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CorsMetrics {
+    pub rejected_origins: u64,
+    pub rejected_methods: u64,
+    pub rejected_headers: u64,
+    pub unknown_origins: BTreeMap<String, u64>,
+}
+
+/// What one CORS policy allows, as seen by [`CorsMonitor`].
+struct MonitoredPolicy {
+    /// Shared with [`ReloadableOrigins`] when built from one.
+    origins: Arc<RwLock<Arc<OriginAllowlist>>>,
+    any_method: bool,
+    methods: Vec<Method>,
+    any_header: bool,
+    headers: Vec<HeaderName>,
+}
+
+impl MonitoredPolicy {
+    fn new(config: &CorsConfig) -> Result<Self, SecurityConfigError> {
+        let origins = Arc::new(RwLock::new(Arc::new(OriginAllowlist::new(config)?)));
+        Self::with_origins(config, origins)
+    }
+
+    fn with_origins(
+        config: &CorsConfig,
+        origins: Arc<RwLock<Arc<OriginAllowlist>>>,
+    ) -> Result<Self, SecurityConfigError> {
+        config.validate()?;
+        Ok(Self {
+            origins,
+            any_method: is_wildcard(&config.allowed_methods),
+            methods: config.methods()?,
+            any_header: is_wildcard(&config.allowed_headers),
+            headers: config.headers()?,
+        })
+    }
+
+    fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        self.origins
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .allows(origin)
+    }
+}
+
+impl CorsMonitor {
+    pub fn new(config: &CorsConfig) -> Result<Self, SecurityConfigError> {
+        Ok(Self::from_policies(
+            Vec::new(),
+            vec![MonitoredPolicy::new(config)?],
+        ))
+    }
+
+    /// Checks origins against the live allowlist, so origins added by a reload
+    /// are not reported as rejected.
+    pub fn for_reloadable(origins: &ReloadableOrigins) -> Result<Self, SecurityConfigError> {
+        let policy = MonitoredPolicy::with_origins(&origins.config, Arc::clone(&origins.current))?;
+        Ok(Self::from_policies(Vec::new(), vec![policy]))
+    }
+
+    /// Checks each request against the profile its path selects, as
+    /// [`CorsProfiles::into_layer`] does.
+    pub fn for_profiles(profiles: &CorsProfiles) -> Result<Self, SecurityConfigError> {
+        let mut policies = profiles
+            .profiles
+            .values()
+            .map(|profile| MonitoredPolicy::new(&profile.cors))
+            .collect::<Result<Vec<_>, _>>()?;
+        policies.push(MonitoredPolicy::new(&profiles.fallback)?);
+        Ok(Self::from_policies(profiles.indexed_routes(), policies))
+    }
+
+    fn from_policies(routes: Vec<(String, usize)>, policies: Vec<MonitoredPolicy>) -> Self {
+        Self {
+            inner: Arc::new(CorsMonitorInner {
+                routes,
+                policies,
+                sample_every: AtomicU64::new(100),
+                rejected_origins: AtomicU64::new(0),
+                rejected_methods: AtomicU64::new(0),
+                rejected_headers: AtomicU64::new(0),
+                unknown_origins: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Logs the first request from each unknown origin, then every `n`th one.
+    pub fn sample_every(self, n: u64) -> Self {
+        self.inner.sample_every.store(n.max(1), Ordering::Relaxed);
+        self
+    }
+
+    pub fn metrics(&self) -> CorsMetrics {
+        let inner = &self.inner;
+        CorsMetrics {
+            rejected_origins: inner.rejected_origins.load(Ordering::Relaxed),
+            rejected_methods: inner.rejected_methods.load(Ordering::Relaxed),
+            rejected_headers: inner.rejected_headers.load(Ordering::Relaxed),
+            unknown_origins: inner
+                .unknown_origins
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .map(|(origin, count)| (origin.clone(), *count))
+                .collect(),
+        }
+    }
+
+    fn inspect(&self, parts: &Parts) {
+        let inner = &self.inner;
+        let Some(origin) = parts.headers.get(header::ORIGIN) else {
+            return;
+        };
+        if is_same_origin(origin, parts) {
+            return;
+        }
+        let origin_str = origin.to_str().unwrap_or("<non-utf8>");
+        let path = parts.uri.path();
+        let policy =
+            &inner.policies[route_index(&inner.routes, path).unwrap_or(inner.policies.len() - 1)];
+
+        if !policy.allows_origin(origin) {
+            inner.rejected_origins.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!(origin = origin_str, %path, reason = "origin", "CORS request rejected");
+            self.record_unknown_origin(origin_str);
+            return;
+        }
+
+        let is_preflight = parts.method == Method::OPTIONS;
+        let Some(requested_method) = parts
+            .headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .filter(|_| is_preflight)
+        else {
+            return;
+        };
+
+        let method_allowed = policy.any_method
+            || Method::from_bytes(requested_method.as_bytes())
+                .is_ok_and(|method| policy.methods.contains(&method));
+        if !method_allowed {
+            inner.rejected_methods.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!(
+                origin = origin_str,
+                %path,
+                method = requested_method.to_str().unwrap_or("<non-utf8>"),
+                reason = "method",
+                "CORS preflight rejected"
+            );
+        }
+
+        if policy.any_header {
+            return;
+        }
+        let denied: Vec<&str> = parts
+            .headers
+            .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter(|name| {
+                !HeaderName::from_bytes(name.as_bytes())
+                    .is_ok_and(|name| policy.headers.contains(&name))
+            })
+            .collect();
+        if !denied.is_empty() {
+            inner.rejected_headers.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!(
+                origin = origin_str,
+                %path,
+                headers = ?denied,
+                reason = "headers",
+                "CORS preflight rejected"
+            );
+        }
+    }
+
+    fn record_unknown_origin(&self, origin: &str) {
+        let mut seen = self
+            .inner
+            .unknown_origins
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if !seen.contains_key(origin) && seen.len() >= MAX_TRACKED_ORIGINS {
+            return;
+        }
+        let count = seen.entry(origin.to_string()).or_default();
+        *count += 1;
+
+        let sample_every = self.inner.sample_every.load(Ordering::Relaxed);
+        if *count == 1 || *count % sample_every == 0 {
+            tracing::warn!(
+                origin,
+                seen = *count,
+                "request from an origin outside the CORS allowlist"
+            );
+        }
+    }
+}
+
+fn is_same_origin(origin: &HeaderValue, parts: &Parts) -> bool {
+    let Some(host) = parts.headers.get(header::HOST) else {
+        return false;
+    };
+    origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .is_some_and(|(_, authority)| authority.as_bytes() == host.as_bytes())
+}
+
+pub async fn log_cors_rejections<B>(
+    State(monitor): State<CorsMonitor>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    monitor.inspect(&parts);
+    next.run(Request::from_parts(parts, body)).await
+}
+
+pub fn cors_layer() -> Result<CorsLayer, SecurityConfigError> {
+    CorsConfig::from_env()?.into_layer()
+}
+
+/// Source-list directives of a Content-Security-Policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Directive {
+    DefaultSrc,
+    ScriptSrc,
+    ScriptSrcElem,
+    ScriptSrcAttr,
+    StyleSrc,
+    StyleSrcElem,
+    StyleSrcAttr,
+    ImgSrc,
+    FontSrc,
+    ConnectSrc,
+    MediaSrc,
+    ObjectSrc,
+    ChildSrc,
+    FrameSrc,
+    WorkerSrc,
+    ManifestSrc,
+    BaseUri,
+    FormAction,
+    FrameAncestors,
+}
+
+const DIRECTIVES: [Directive; 19] = [
+    Directive::DefaultSrc,
+    Directive::ScriptSrc,
+    Directive::ScriptSrcElem,
+    Directive::ScriptSrcAttr,
+    Directive::StyleSrc,
+    Directive::StyleSrcElem,
+    Directive::StyleSrcAttr,
+    Directive::ImgSrc,
+    Directive::FontSrc,
+    Directive::ConnectSrc,
+    Directive::MediaSrc,
+    Directive::ObjectSrc,
+    Directive::ChildSrc,
+    Directive::FrameSrc,
+    Directive::WorkerSrc,
+    Directive::ManifestSrc,
+    Directive::BaseUri,
+    Directive::FormAction,
+    Directive::FrameAncestors,
+];
+
+impl Directive {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::DefaultSrc => "default-src",
+            Self::ScriptSrc => "script-src",
+            Self::ScriptSrcElem => "script-src-elem",
+            Self::ScriptSrcAttr => "script-src-attr",
+            Self::StyleSrc => "style-src",
+            Self::StyleSrcElem => "style-src-elem",
+            Self::StyleSrcAttr => "style-src-attr",
+            Self::ImgSrc => "img-src",
+            Self::FontSrc => "font-src",
+            Self::ConnectSrc => "connect-src",
+            Self::MediaSrc => "media-src",
+            Self::ObjectSrc => "object-src",
+            Self::ChildSrc => "child-src",
+            Self::FrameSrc => "frame-src",
+            Self::WorkerSrc => "worker-src",
+            Self::ManifestSrc => "manifest-src",
+            Self::BaseUri => "base-uri",
+            Self::FormAction => "form-action",
+            Self::FrameAncestors => "frame-ancestors",
+        }
+    }
+
+    /// The directives browsers consult, in order, when this one is absent.
+    /// `child-src` falls back to `script-src` only on behalf of `worker-src`.
+    pub fn fallbacks(self) -> &'static [Self] {
+        match self {
+            Self::DefaultSrc | Self::BaseUri | Self::FormAction | Self::FrameAncestors => &[],
+            Self::ScriptSrcElem | Self::ScriptSrcAttr => &[Self::ScriptSrc, Self::DefaultSrc],
+            Self::StyleSrcElem | Self::StyleSrcAttr => &[Self::StyleSrc, Self::DefaultSrc],
+            Self::FrameSrc => &[Self::ChildSrc, Self::DefaultSrc],
+            Self::WorkerSrc => &[Self::ChildSrc, Self::ScriptSrc, Self::DefaultSrc],
+            _ => &[Self::DefaultSrc],
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        DIRECTIVES
+            .into_iter()
+            .find(|directive| directive.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        }
+    }
+}
+
+/// A CSP source expression.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Source {
+    None,
+    SelfOrigin,
+    UnsafeInline,
+    UnsafeEval,
+    UnsafeHashes,
+    StrictDynamic,
+    WasmUnsafeEval,
+    ReportSample,
+    /// `https:`, `data:`, `blob:` and so on, without the colon.
+    Scheme(String),
+    /// `example.com`, `*.example.com`, `https://cdn.example.com:443/path`.
+    Host(String),
+    /// Base64 nonce, without the `'nonce-` wrapper.
+    Nonce(String),
+    /// Base64 digest, without the `'sha256-` wrapper.
+    Hash(HashAlgorithm, String),
+}
+
+impl Source {
+    pub fn host(host: impl Into<String>) -> Self {
+        Self::Host(host.into())
+    }
+
+    pub fn scheme(scheme: impl Into<String>) -> Self {
+        Self::Scheme(scheme.into().trim_end_matches(':').to_ascii_lowercase())
+    }
+
+    pub fn nonce(nonce: impl Into<String>) -> Self {
+        Self::Nonce(nonce.into())
+    }
+
+    pub fn sha256(digest_base64: impl Into<String>) -> Self {
+        Self::Hash(HashAlgorithm::Sha256, digest_base64.into())
+    }
+
+    /// `'sha256-…'` source for an inline `<script>` or `<style>` body. Hash the
+    /// exact text between the tags, whitespace included.
+    pub fn sha256_of(content: impl AsRef<[u8]>) -> Self {
+        Self::sha256(BASE64.encode(Sha256::digest(content.as_ref())))
+    }
+
+    fn validate(&self) -> Result<(), SecurityConfigError> {
+        let problem = match self {
+            Self::Scheme(scheme) => {
+                let mut chars = scheme.chars();
+                let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                    && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+                (!valid).then_some("scheme sources look like `https:`")
+            }
+            Self::Host(host) => {
+                let invalid = host.is_empty()
+                    || host.chars().any(|c| {
+                        c.is_whitespace() || c.is_control() || matches!(c, '\'' | ';' | ',')
+                    });
+                invalid.then_some("host sources must not contain quotes, `;`, `,` or spaces")
+            }
+            Self::Nonce(value) | Self::Hash(_, value) => {
+                let valid = !value.is_empty()
+                    && value.chars().all(|c| {
+                        c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '-' | '_')
+                    });
+                (!valid).then_some("nonces and hashes must be base64")
+            }
+            _ => None,
+        };
+
+        match problem {
+            Some(reason) => Err(SecurityConfigError::InvalidCsp {
+                token: self.to_string(),
+                reason,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("'none'"),
+            Self::SelfOrigin => f.write_str("'self'"),
+            Self::UnsafeInline => f.write_str("'unsafe-inline'"),
+            Self::UnsafeEval => f.write_str("'unsafe-eval'"),
+            Self::UnsafeHashes => f.write_str("'unsafe-hashes'"),
+            Self::StrictDynamic => f.write_str("'strict-dynamic'"),
+            Self::WasmUnsafeEval => f.write_str("'wasm-unsafe-eval'"),
+            Self::ReportSample => f.write_str("'report-sample'"),
+            Self::Scheme(scheme) => write!(f, "{scheme}:"),
+            Self::Host(host) => f.write_str(host),
+            Self::Nonce(nonce) => write!(f, "'nonce-{nonce}'"),
+            Self::Hash(algorithm, digest) => write!(f, "'{}-{digest}'", algorithm.prefix()),
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = SecurityConfigError;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let lower = token.to_ascii_lowercase();
+        let source = if let Some(keyword) = lower
+            .strip_prefix('\'')
+            .and_then(|rest| rest.strip_suffix('\''))
+        {
+            // Nonces and hashes are case-sensitive, so slice the original token.
+            let value = &token[1..token.len() - 1];
+            match keyword {
+                "none" => Self::None,
+                "self" => Self::SelfOrigin,
+                "unsafe-inline" => Self::UnsafeInline,
+                "unsafe-eval" => Self::UnsafeEval,
+                "unsafe-hashes" => Self::UnsafeHashes,
+                "strict-dynamic" => Self::StrictDynamic,
+                "wasm-unsafe-eval" => Self::WasmUnsafeEval,
+                "report-sample" => Self::ReportSample,
+                _ if keyword.starts_with("nonce-") => Self::Nonce(value[6..].to_string()),
+                _ if keyword.starts_with("sha256-") => {
+                    Self::Hash(HashAlgorithm::Sha256, value[7..].to_string())
+                }
+                _ if keyword.starts_with("sha384-") => {
+                    Self::Hash(HashAlgorithm::Sha384, value[7..].to_string())
+                }
+                _ if keyword.starts_with("sha512-") => {
+                    Self::Hash(HashAlgorithm::Sha512, value[7..].to_string())
+                }
+                _ => {
+                    return Err(SecurityConfigError::InvalidCsp {
+                        token: token.to_string(),
+                        reason: "unknown keyword source",
+                    })
+                }
+            }
+        } else if let Some(scheme) = lower.strip_suffix(':') {
+            Self::Scheme(scheme.to_string())
+        } else {
+            Self::Host(token.to_string())
+        };
+
+        source.validate()?;
+        Ok(source)
+    }
+}
+
+/// Typed Content-Security-Policy that serializes to a header value and parses
+/// existing policy strings, so policies can be diffed and merged.
+///
+/// ```ignore
+/// let csp = ContentSecurityPolicy::new()
+///     .directive(Directive::DefaultSrc, [Source::SelfOrigin])
+///     .directive(Directive::ScriptSrc, [Source::SelfOrigin, Source::host("cdn.example.com")])
+///     .directive(Directive::FrameAncestors, [Source::None]);
+/// let csp: ContentSecurityPolicy = "default-src 'self'; img-src https:".parse()?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentSecurityPolicy {
+    sources: BTreeMap<Directive, Vec<Source>>,
+    upgrade_insecure_requests: bool,
+    sandbox: Option<Vec<String>>,
+    require_trusted_types: bool,
+    trusted_types: Option<Vec<String>>,
+    report_uri: Vec<String>,
+    report_to: Option<String>,
+    /// Directives this type does not model, such as `block-all-mixed-content` or
+    /// `webrtc`, kept verbatim with their values.
+    other: Vec<(String, Vec<String>)>,
+}
+
+/// Directive/value pairs present in one policy but not the other.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CspDiff {
+    pub added: Vec<(String, String)>,
+    pub removed: Vec<(String, String)>,
+}
+
+impl CspDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl ContentSecurityPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the sources of `directive`.
+    pub fn directive(
+        mut self,
+        directive: Directive,
+        sources: impl IntoIterator<Item = Source>,
+    ) -> Self {
+        self.sources
+            .insert(directive, sources.into_iter().collect());
+        self
+    }
+
+    /// Adds a source to `directive`, dropping `'none'` which cannot be combined
+    /// with other sources. A directive that is not set yet starts from its
+    /// fallback's sources, so adding a source never narrows the policy.
+    pub fn add_source(mut self, directive: Directive, source: Source) -> Self {
+        self.push_source(directive, source);
+        self
+    }
+
+    pub fn upgrade_insecure_requests(mut self, enabled: bool) -> Self {
+        self.upgrade_insecure_requests = enabled;
+        self
+    }
+
+    /// Enables `sandbox` with the given `allow-*` tokens.
+    pub fn sandbox(mut self, allow: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.sandbox = Some(allow.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// `require-trusted-types-for 'script'`: DOM XSS sinks such as `innerHTML`
+    /// only accept values created by a Trusted Types policy.
+    pub fn require_trusted_types(mut self, enabled: bool) -> Self {
+        self.require_trusted_types = enabled;
+        self
+    }
+
+    /// `trusted-types` with the policy names the page may create, plus
+    /// `'allow-duplicates'` or `'none'`.
+    pub fn trusted_types(mut self, policies: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.trusted_types = Some(policies.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn report_uri(mut self, uri: impl Into<String>) -> Self {
+        self.report_uri.push(uri.into());
+        self
+    }
+
+    pub fn report_to(mut self, group: impl Into<String>) -> Self {
+        self.report_to = Some(group.into());
+        self
+    }
+
+    pub fn sources(&self, directive: Directive) -> Option<&[Source]> {
+        self.sources.get(&directive).map(Vec::as_slice)
+    }
+
+    /// Sources that apply to `directive`, following the fallback chain.
+    pub fn effective_sources(&self, directive: Directive) -> Option<&[Source]> {
+        std::iter::once(directive)
+            .chain(directive.fallbacks().iter().copied())
+            .find_map(|directive| self.sources(directive))
+    }
+
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        for sources in self.sources.values() {
+            for source in sources {
+                source.validate()?;
+            }
+            if sources.len() > 1 && sources.contains(&Source::None) {
+                return Err(SecurityConfigError::InvalidCsp {
+                    token: "'none'".into(),
+                    reason: "'none' cannot be combined with other sources",
+                });
+            }
+        }
+        for policy in self.trusted_types.iter().flatten() {
+            let reason = match policy.as_str() {
+                "'allow-duplicates'" | "*" => continue,
+                "'none'"
+                    if self
+                        .trusted_types
+                        .as_ref()
+                        .is_some_and(|names| names.len() > 1) =>
+                {
+                    "'none' cannot be combined with policy names"
+                }
+                "'none'" => continue,
+                name if !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-#=_/@.%".contains(c)) =>
+                {
+                    continue
+                }
+                _ => "invalid Trusted Types policy name",
+            };
+            return Err(SecurityConfigError::InvalidCsp {
+                token: policy.clone(),
+                reason,
+            });
+        }
+        for token in self.report_uri.iter().chain(&self.report_to) {
+            if token.is_empty() || token.contains(|c: char| c.is_whitespace() || c == ';') {
+                return Err(SecurityConfigError::InvalidCsp {
+                    token: token.clone(),
+                    reason: "report destinations must not contain spaces or `;`",
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_header_value(&self) -> Result<HeaderValue, SecurityConfigError> {
+        self.validate()?;
+        header_value("content-security-policy", &self.to_string())
+    }
+
+    /// Adds everything `other` allows. Directives missing here start from their
+    /// fallback sources, so merging never narrows what `default-src` permitted.
+    pub fn merge(&mut self, other: &Self) {
+        for (directive, sources) in &other.sources {
+            for source in sources {
+                self.push_source(*directive, source.clone());
+            }
+        }
+        self.upgrade_insecure_requests |= other.upgrade_insecure_requests;
+        self.require_trusted_types |= other.require_trusted_types;
+        if let Some(policies) = &other.trusted_types {
+            let merged = self.trusted_types.get_or_insert_with(Vec::new);
+            for policy in policies {
+                if !merged.contains(policy) {
+                    merged.push(policy.clone());
+                }
+            }
+            if merged.len() > 1 {
+                merged.retain(|policy| policy != "'none'");
+            }
+        }
+        if let Some(allow) = &other.sandbox {
+            let sandbox = self.sandbox.get_or_insert_with(Vec::new);
+            for token in allow {
+                if !sandbox.contains(token) {
+                    sandbox.push(token.clone());
+                }
+            }
+        }
+        for uri in &other.report_uri {
+            if !self.report_uri.contains(uri) {
+                self.report_uri.push(uri.clone());
+            }
+        }
+        if self.report_to.is_none() {
+            self.report_to = other.report_to.clone();
+        }
+        for (name, values) in &other.other {
+            match self
+                .other
+                .iter_mut()
+                .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+            {
+                Some((_, merged)) => {
+                    for value in values {
+                        if !merged.contains(value) {
+                            merged.push(value.clone());
+                        }
+                    }
+                }
+                None => self.other.push((name.clone(), values.clone())),
+            }
+        }
+    }
+
+    /// Allows one known inline script by hash, e.g.
+    /// `allow_inline_script(include_str!("../static/bootstrap.js"))`.
+    pub fn allow_inline_script(self, content: impl AsRef<[u8]>) -> Self {
+        self.add_hash(Directive::ScriptSrc, Source::sha256_of(content))
+    }
+
+    /// Allows one known inline style by hash and drops `'unsafe-inline'` from
+    /// `style-src`, which browsers ignore once a hash is present.
+    pub fn allow_inline_style(self, content: impl AsRef<[u8]>) -> Self {
+        self.add_hash(Directive::StyleSrc, Source::sha256_of(content))
+    }
+
+    pub fn allow_inline_script_file(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, SecurityConfigError> {
+        Ok(self.allow_inline_script(read_inline_file(path.as_ref())?))
+    }
+
+    pub fn allow_inline_style_file(
+        self,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, SecurityConfigError> {
+        Ok(self.allow_inline_style(read_inline_file(path.as_ref())?))
+    }
+
+    fn add_hash(mut self, directive: Directive, hash: Source) -> Self {
+        self.push_source(directive, hash);
+        if let Some(sources) = self.sources.get_mut(&directive) {
+            sources.retain(|source| *source != Source::UnsafeInline);
+        }
+        self
+    }
+
+    /// Copy of the policy that allows inline `<script>` and `<style>` elements
+    /// carrying `nonce`. Directives the policy does not restrict, even through
+    /// `default-src`, are left alone, since a nonce alone would restrict them.
+    pub fn with_nonce(&self, nonce: &CspNonce) -> Self {
+        let mut policy = self.clone();
+        for directive in [Directive::ScriptSrc, Directive::StyleSrc] {
+            if self.effective_sources(directive).is_some() {
+                policy.push_source(directive, Source::nonce(nonce.as_str()));
+            }
+        }
+        policy
+    }
+
+    pub fn diff(&self, other: &Self) -> CspDiff {
+        let ours = self.tokens();
+        let theirs = other.tokens();
+        CspDiff {
+            added: theirs.difference(&ours).cloned().collect(),
+            removed: ours.difference(&theirs).cloned().collect(),
+        }
+    }
+
+    fn push_source(&mut self, directive: Directive, source: Source) {
+        if !self.sources.contains_key(&directive) {
+            let inherited = self
+                .effective_sources(directive)
+                .map(<[_]>::to_vec)
+                .unwrap_or_default();
+            self.sources.insert(directive, inherited);
+        }
+        let sources = self.sources.entry(directive).or_default();
+        if source != Source::None {
+            sources.retain(|existing| *existing != Source::None);
+        } else if !sources.is_empty() {
+            return;
+        }
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+
+    fn tokens(&self) -> BTreeSet<(String, String)> {
+        let mut tokens = BTreeSet::new();
+        for (directive, sources) in &self.sources {
+            for source in sources {
+                tokens.insert((directive.name().to_string(), source.to_string()));
+            }
+        }
+        if self.upgrade_insecure_requests {
+            tokens.insert(("upgrade-insecure-requests".into(), String::new()));
+        }
+        if let Some(allow) = &self.sandbox {
+            tokens.insert(("sandbox".into(), String::new()));
+            for token in allow {
+                tokens.insert(("sandbox".into(), token.clone()));
+            }
+        }
+        if self.require_trusted_types {
+            tokens.insert(("require-trusted-types-for".into(), "'script'".into()));
+        }
+        if let Some(policies) = &self.trusted_types {
+            tokens.insert(("trusted-types".into(), String::new()));
+            for policy in policies {
+                tokens.insert(("trusted-types".into(), policy.clone()));
+            }
+        }
+        for uri in &self.report_uri {
+            tokens.insert(("report-uri".into(), uri.clone()));
+        }
+        if let Some(group) = &self.report_to {
+            tokens.insert(("report-to".into(), group.clone()));
+        }
+        for (name, values) in &self.other {
+            tokens.insert((name.to_ascii_lowercase(), String::new()));
+            for value in values {
+                tokens.insert((name.to_ascii_lowercase(), value.clone()));
+            }
+        }
+        tokens
+    }
+}
+
+fn read_inline_file(path: &Path) -> Result<Vec<u8>, SecurityConfigError> {
+    std::fs::read(path).map_err(|source| SecurityConfigError::ReadConfig {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+impl fmt::Display for ContentSecurityPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        for (directive, sources) in &self.sources {
+            let sources = sources.iter().map(ToString::to_string).collect::<Vec<_>>();
+            parts.push(
+                format!("{directive} {}", sources.join(" "))
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+        if self.upgrade_insecure_requests {
+            parts.push("upgrade-insecure-requests".into());
+        }
+        if let Some(allow) = &self.sandbox {
+            parts.push(
+                format!("sandbox {}", allow.join(" "))
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+        if self.require_trusted_types {
+            parts.push("require-trusted-types-for 'script'".into());
+        }
+        if let Some(policies) = &self.trusted_types {
+            parts.push(
+                format!("trusted-types {}", policies.join(" "))
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+        for (name, values) in &self.other {
+            parts.push(
+                format!("{name} {}", values.join(" "))
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+        if !self.report_uri.is_empty() {
+            parts.push(format!("report-uri {}", self.report_uri.join(" ")));
+        }
+        if let Some(group) = &self.report_to {
+            parts.push(format!("report-to {group}"));
+        }
+        f.write_str(&parts.join("; "))
+    }
+}
+
+impl FromStr for ContentSecurityPolicy {
+    type Err = SecurityConfigError;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        let mut csp = Self::new();
+
+        for directive in policy.split(';') {
+            let mut tokens = directive.split_whitespace();
+            let Some(name) = tokens.next() else {
+                continue;
+            };
+            let values: Vec<&str> = tokens.collect();
+            let unexpected_value = |reason| SecurityConfigError::InvalidCsp {
+                token: directive.trim().to_string(),
+                reason,
+            };
+
+            match name.to_ascii_lowercase().as_str() {
+                "upgrade-insecure-requests" => {
+                    if !values.is_empty() {
+                        return Err(unexpected_value("upgrade-insecure-requests takes no value"));
+                    }
+                    csp.upgrade_insecure_requests = true;
+                }
+                "sandbox" => csp.sandbox = Some(values.iter().map(|v| v.to_string()).collect()),
+                "require-trusted-types-for" => match values.as_slice() {
+                    [sink] if sink.eq_ignore_ascii_case("'script'") => {
+                        csp.require_trusted_types = true
+                    }
+                    _ => {
+                        return Err(unexpected_value(
+                            "require-trusted-types-for only supports 'script'",
+                        ))
+                    }
+                },
+                "trusted-types" => {
+                    csp.trusted_types = Some(values.iter().map(|v| v.to_string()).collect())
+                }
+                "report-uri" => csp.report_uri.extend(values.iter().map(|v| v.to_string())),
+                "report-to" => match values.as_slice() {
+                    [group] => csp.report_to = Some(group.to_string()),
+                    _ => return Err(unexpected_value("report-to takes one group name")),
+                },
+                _ => {
+                    let Some(directive) = Directive::from_name(name) else {
+                        // Newer or legacy directives pass through untouched, so a
+                        // policy that browsers accept keeps working here.
+                        if csp
+                            .other
+                            .iter()
+                            .any(|(existing, _)| existing.eq_ignore_ascii_case(name))
+                        {
+                            return Err(unexpected_value(
+                                "duplicate directive; browsers ignore all but the first",
+                            ));
+                        }
+                        tracing::debug!(directive = name, "keeping unrecognized CSP directive");
+                        csp.other.push((
+                            name.to_string(),
+                            values.iter().map(|v| v.to_string()).collect(),
+                        ));
+                        continue;
+                    };
+                    if csp.sources.contains_key(&directive) {
+                        return Err(unexpected_value(
+                            "duplicate directive; browsers ignore all but the first",
+                        ));
+                    }
+                    let sources = values
+                        .iter()
+                        .map(|value| value.parse())
+                        .collect::<Result<Vec<Source>, _>>()?;
+                    csp.sources.insert(directive, sources);
+                }
+            }
+        }
+
+        csp.validate()?;
+        Ok(csp)
+    }
+}
+
+/// Browser features controlled by `Permissions-Policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Feature {
+    Accelerometer,
+    AmbientLightSensor,
+    Autoplay,
+    Bluetooth,
+    BrowsingTopics,
+    Camera,
+    DisplayCapture,
+    EncryptedMedia,
+    Fullscreen,
+    Geolocation,
+    Gyroscope,
+    Hid,
+    IdleDetection,
+    Magnetometer,
+    Microphone,
+    Midi,
+    Payment,
+    PictureInPicture,
+    PublickeyCredentialsGet,
+    ScreenWakeLock,
+    Serial,
+    SyncXhr,
+    Usb,
+    WebShare,
+    XrSpatialTracking,
+}
+
+const FEATURES: [Feature; 25] = [
+    Feature::Accelerometer,
+    Feature::AmbientLightSensor,
+    Feature::Autoplay,
+    Feature::Bluetooth,
+    Feature::BrowsingTopics,
+    Feature::Camera,
+    Feature::DisplayCapture,
+    Feature::EncryptedMedia,
+    Feature::Fullscreen,
+    Feature::Geolocation,
+    Feature::Gyroscope,
+    Feature::Hid,
+    Feature::IdleDetection,
+    Feature::Magnetometer,
+    Feature::Microphone,
+    Feature::Midi,
+    Feature::Payment,
+    Feature::PictureInPicture,
+    Feature::PublickeyCredentialsGet,
+    Feature::ScreenWakeLock,
+    Feature::Serial,
+    Feature::SyncXhr,
+    Feature::Usb,
+    Feature::WebShare,
+    Feature::XrSpatialTracking,
+];
+
+impl Feature {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Accelerometer => "accelerometer",
+            Self::AmbientLightSensor => "ambient-light-sensor",
+            Self::Autoplay => "autoplay",
+            Self::Bluetooth => "bluetooth",
+            Self::BrowsingTopics => "browsing-topics",
+            Self::Camera => "camera",
+            Self::DisplayCapture => "display-capture",
+            Self::EncryptedMedia => "encrypted-media",
+            Self::Fullscreen => "fullscreen",
+            Self::Geolocation => "geolocation",
+            Self::Gyroscope => "gyroscope",
+            Self::Hid => "hid",
+            Self::IdleDetection => "idle-detection",
+            Self::Magnetometer => "magnetometer",
+            Self::Microphone => "microphone",
+            Self::Midi => "midi",
+            Self::Payment => "payment",
+            Self::PictureInPicture => "picture-in-picture",
+            Self::PublickeyCredentialsGet => "publickey-credentials-get",
+            Self::ScreenWakeLock => "screen-wake-lock",
+            Self::Serial => "serial",
+            Self::SyncXhr => "sync-xhr",
+            Self::Usb => "usb",
+            Self::WebShare => "web-share",
+            Self::XrSpatialTracking => "xr-spatial-tracking",
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = SecurityConfigError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        FEATURES
+            .into_iter()
+            .find(|feature| feature.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| SecurityConfigError::InvalidPermissionsPolicy {
+                token: name.to_string(),
+                reason: "unknown feature",
+            })
+    }
+}
+
+/// Who may use a feature: `self`, any origin (`*`) or a specific origin.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Allow {
+    SelfOrigin,
+    All,
+    Origin(String),
+}
+
+impl Allow {
+    pub fn origin(origin: impl Into<String>) -> Self {
+        Self::Origin(origin.into())
+    }
+}
+
+impl FromStr for Allow {
+    type Err = SecurityConfigError;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let token = token.trim().trim_matches('"');
+        match token {
+            "self" => Ok(Self::SelfOrigin),
+            "*" => Ok(Self::All),
+            _ => parse_origin(token)
+                .map(|_| Self::Origin(token.trim_end_matches('/').to_string()))
+                .map_err(|_| SecurityConfigError::InvalidPermissionsPolicy {
+                    token: token.to_string(),
+                    reason: "expected `self`, `*` or an origin such as `https://example.com`",
+                }),
+        }
+    }
+}
+
+/// Typed `Permissions-Policy` that starts from denying every known feature.
+///
+/// ```ignore
+/// let policy = PermissionsPolicy::deny_all()
+///     .allow(Feature::Fullscreen, [Allow::SelfOrigin])
+///     .allow(Feature::Payment, [Allow::SelfOrigin, Allow::origin("https://pay.example.com")]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionsPolicy {
+    features: BTreeMap<Feature, Vec<Allow>>,
+}
+
+impl Default for PermissionsPolicy {
+    fn default() -> Self {
+        Self::deny_all()
+    }
+}
+
+impl PermissionsPolicy {
+    pub fn deny_all() -> Self {
+        Self {
+            features: FEATURES
+                .into_iter()
+                .map(|feature| (feature, Vec::new()))
+                .collect(),
+        }
+    }
+
+    /// Replaces the allowlist of `feature`. An empty allowlist denies it.
+    pub fn allow(mut self, feature: Feature, allow: impl IntoIterator<Item = Allow>) -> Self {
+        let mut allow: Vec<Allow> = allow.into_iter().collect();
+        if allow.contains(&Allow::All) {
+            allow = vec![Allow::All];
+        }
+        self.features.insert(feature, allow);
+        self
+    }
+
+    pub fn deny(self, feature: Feature) -> Self {
+        self.allow(feature, [])
+    }
+
+    /// Leaves `feature` out of the header so the browser default applies.
+    pub fn unset(mut self, feature: Feature) -> Self {
+        self.features.remove(&feature);
+        self
+    }
+
+    pub fn to_header_value(&self) -> Result<HeaderValue, SecurityConfigError> {
+        header_value("permissions-policy", &self.to_string())
+    }
+}
+
+impl fmt::Display for PermissionsPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self
+            .features
+            .iter()
+            .map(|(feature, allow)| {
+                if allow.contains(&Allow::All) {
+                    return format!("{}=*", feature.name());
+                }
+                let allow: Vec<String> = allow
+                    .iter()
+                    .map(|allow| match allow {
+                        Allow::SelfOrigin => "self".to_string(),
+                        Allow::All => "*".to_string(),
+                        Allow::Origin(origin) => format!("\"{origin}\""),
+                    })
+                    .collect();
+                format!("{}=({})", feature.name(), allow.join(" "))
+            })
+            .collect();
+        f.write_str(&entries.join(", "))
+    }
+}
+
+/// Per-header setting: `true` sends the default value, `false` omits the header
+/// and a string replaces the value.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum HeaderSetting {
+    Enabled(bool),
+    Value(String),
+}
+
+impl Default for HeaderSetting {
+    fn default() -> Self {
+        Self::Enabled(true)
+    }
+}
+
+impl HeaderSetting {
+    fn resolve(&self, default: &str) -> Option<String> {
+        match self {
+            Self::Enabled(false) => None,
+            Self::Enabled(true) => Some(default.to_string()),
+            Self::Value(value) => Some(value.clone()),
+        }
+    }
+}
+
+const DEFAULT_CSP: &str = "default-src 'self'; \
+     script-src 'self'; \
+     style-src 'self' 'unsafe-inline'; \
+     img-src 'self' data: https:; \
+     font-src 'self'; \
+     connect-src 'self'; \
+     frame-ancestors 'none';";
+const DEFAULT_REFERRER_POLICY: &str = "strict-origin-when-cross-origin";
+/// Headers that reveal the server software or framework version.
+const DEFAULT_STRIPPED_HEADERS: [&str; 8] = [
+    "server",
+    "x-powered-by",
+    "x-aspnet-version",
+    "x-aspnetmvc-version",
+    "x-runtime",
+    "x-generator",
+    "x-backend-server",
+    "x-drupal-cache",
+];
+
+/// Response headers added by [`SecurityHeadersLayer`], loaded from the
+/// `[security_headers]` table of a TOML file.
+///
+/// ```toml
+/// [security_headers]
+/// x_frame_options = "SAMEORIGIN"
+///
+/// [security_headers.hsts]
+/// max_age_secs = 63072000
+/// preload = true
+///
+/// [security_headers.trusted_proxies]
+/// addresses = ["10.0.0.2"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SecurityHeadersConfig {
+    pub content_security_policy: HeaderSetting,
+    /// Only sent on HTTPS requests, see [`TrustedProxies::is_https`]; `true`
+    /// builds the value from `hsts`.
+    pub strict_transport_security: HeaderSetting,
+    pub hsts: HstsConfig,
+    /// Treat every request as HTTPS, for apps only reachable over TLS that do
+    /// not add [`TlsConnection`].
+    pub assume_https: bool,
+    /// Proxies whose `Forwarded`/`X-Forwarded-Proto` decide whether a request
+    /// arrived over HTTPS.
+    pub trusted_proxies: TrustedProxies,
+    pub x_content_type_options: HeaderSetting,
+    pub x_frame_options: HeaderSetting,
+    pub referrer_policy: HeaderSetting,
+    /// Generate a [`CspNonce`] per request, store it in the request extensions and
+    /// add it to `script-src` and `style-src`. Browsers ignore `'unsafe-inline'`
+    /// in a directive that also has a nonce.
+    pub csp_nonce: bool,
+    /// Inline scripts to allow by `sha256-` hash, hashed when the layer is built.
+    pub inline_script_files: Vec<PathBuf>,
+    pub inline_style_files: Vec<PathBuf>,
+    /// Precomputed hashes such as `sha256-abc…=`, e.g. produced by a build script.
+    pub inline_script_hashes: Vec<String>,
+    pub inline_style_hashes: Vec<String>,
+    /// Adds `report-uri` to the CSP, e.g. the path served by [`CspReportCollector`].
+    pub csp_report_uri: Option<String>,
+    /// Candidate policy sent as `Content-Security-Policy-Report-Only` next to the
+    /// enforced one. Browsers report what it would block without blocking it.
+    /// Nonces, inline hashes and `csp_report_uri` apply to both policies.
+    pub content_security_policy_report_only: Option<String>,
+    /// Trusted Types policy names, e.g. `["dompurify", "default"]`. Setting this
+    /// adds `trusted-types` and `require-trusted-types-for 'script'` to the CSP.
+    pub trusted_types: Option<Vec<String>>,
+    /// Put the Trusted Types directives in the report-only policy instead, so
+    /// code that writes strings to DOM sinks is reported rather than broken.
+    pub trusted_types_report_only: bool,
+    /// `true` denies every [`Feature`] except those listed in `permissions`.
+    pub permissions_policy: HeaderSetting,
+    /// Per-feature allowlists, e.g. `payment = ["self", "https://pay.example.com"]`.
+    pub permissions: BTreeMap<String, Vec<String>>,
+    /// Picks the values `true` stands for in the three cross-origin settings below.
+    pub cross_origin_preset: CrossOriginPreset,
+    pub cross_origin_opener_policy: HeaderSetting,
+    /// Only sent by the `isolated` preset unless set to a value.
+    pub cross_origin_embedder_policy: HeaderSetting,
+    pub cross_origin_resource_policy: HeaderSetting,
+    pub override_mode: OverrideMode,
+    /// Add `Content-Disposition: attachment` when the handler set none, so
+    /// browsers download the response instead of rendering it.
+    pub force_attachment: bool,
+    /// Response headers to remove, whoever set them. Unset strips the common
+    /// fingerprinting headers such as `Server` and `X-Powered-By`; an empty list
+    /// strips nothing.
+    pub strip_headers: Option<Vec<String>>,
+    /// Add `Cache-Control: no-store` and `Pragma: no-cache` when the request
+    /// carried `Authorization` or a session cookie, unless the handler set its
+    /// own `Cache-Control`. Handlers can also opt in with [`SensitiveResponse`].
+    pub no_store_authenticated: bool,
+    /// Cookie names that count as a session. Unset means any cookie does.
+    pub session_cookies: Option<Vec<String>>,
+    /// `Reporting-Endpoints` as name → URL, e.g. `csp = "/csp-report"`. CSP
+    /// `report-to` and `nel.report_to` refer to these names. Sent over HTTPS
+    /// only, like NEL, since the Reporting API needs a secure context.
+    pub reporting_endpoints: BTreeMap<String, String>,
+    /// Network Error Logging, sent over HTTPS only since browsers ignore it on
+    /// plain HTTP.
+    pub nel: Option<NelConfig>,
+}
+
+/// `NEL` policy. Browsers still look NEL groups up in the legacy `Report-To`
+/// header, so one is sent for `report_to` alongside `Reporting-Endpoints`.
+///
+/// ```toml
+/// [security_headers.reporting_endpoints]
+/// network-errors = "https://reports.example.net/nel"
+///
+/// [security_headers.nel]
+/// report_to = "network-errors"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NelConfig {
+    /// A `reporting_endpoints` name whose URL is absolute `https://`.
+    pub report_to: String,
+    pub max_age_secs: u64,
+    pub include_subdomains: bool,
+    /// Share of successful requests to report, from 0.0 to 1.0.
+    pub success_fraction: f64,
+    pub failure_fraction: f64,
+}
+
+impl Default for NelConfig {
+    fn default() -> Self {
+        Self {
+            report_to: String::new(),
+            max_age_secs: 604_800,
+            include_subdomains: false,
+            success_fraction: 0.0,
+            failure_fraction: 1.0,
+        }
+    }
+}
+
+/// Directives for `Strict-Transport-Security`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HstsConfig {
+    pub max_age_secs: u64,
+    pub include_subdomains: bool,
+    /// Off by default: once a domain is on the browser preload list, removing it
+    /// takes months, so only enable this after submitting the domain.
+    pub preload: bool,
+}
+
+impl Default for HstsConfig {
+    fn default() -> Self {
+        Self {
+            max_age_secs: 31_536_000,
+            include_subdomains: true,
+            preload: false,
+        }
+    }
+}
+
+impl fmt::Display for HstsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "max-age={}", self.max_age_secs)?;
+        if self.include_subdomains {
+            f.write_str("; includeSubDomains")?;
+        }
+        if self.preload {
+            f.write_str("; preload")?;
+        }
+        Ok(())
+    }
+}
+
+/// Request extension marking a request that arrived on a TLS connection this
+/// process terminated. The URI cannot tell HTTPS apart from plain HTTP: it has
+/// no scheme in HTTP/1.1 origin form, and the scheme of an absolute-form target
+/// or HTTP/2 `:scheme` is whatever the client sent. Add it as the outermost
+/// layer of the router served on the TLS listener:
+///
+/// ```ignore
+/// let https_app = app.layer(Extension(TlsConnection));
+/// axum_server::bind_rustls(addr, tls_config)
+///     .serve(https_app.into_make_service_with_connect_info::<SocketAddr>())
+///     .await?;
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlsConnection;
+
+/// Reverse proxies allowed to tell us the original scheme. Forwarding headers
+/// from anyone else are ignored, since a client can send them itself.
+///
+/// The peer address comes from [`ConnectInfo<SocketAddr>`], so serve the app
+/// with `into_make_service_with_connect_info::<SocketAddr>()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TrustedProxies {
+    pub addresses: Vec<IpAddr>,
+    /// Trust every peer. Only safe when the app cannot be reached except
+    /// through the proxy, e.g. a private network or a Unix socket.
+    pub trust_all: bool,
+}
+
+impl TrustedProxies {
+    /// Whether the request reached the edge over HTTPS: it carries a
+    /// [`TlsConnection`] extension, or a trusted proxy sent
+    /// `Forwarded: proto=https` or `X-Forwarded-Proto: https`.
+    pub fn is_https<B>(&self, req: &Request<B>) -> bool {
+        self.is_https_transport(&Transport::from_request(req))
+    }
+
+    fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        self.trust_all || peer.is_some_and(|peer| self.addresses.contains(&peer))
+    }
+
+    fn is_https_transport(&self, transport: &Transport) -> bool {
+        transport.tls
+            || (self.trusts(transport.peer)
+                && transport.forwarded_proto.as_deref() == Some("https"))
+    }
+
+    /// The host the client asked for: the trusted proxy's forwarded host if it
+    /// sent one, otherwise `Host`.
+    fn original_host<'a>(&self, transport: &'a Transport) -> Option<&'a str> {
+        transport
+            .forwarded_host
+            .as_deref()
+            .filter(|_| self.trusts(transport.peer))
+            .or(transport.host.as_deref())
+    }
+}
+
+/// How a request reached the server and whether it carried credentials,
+/// captured before the handler runs so the response side can decide on
+/// HTTPS-only and caching headers.
+#[derive(Debug, Clone, Default)]
+struct Transport {
+    /// Marked with [`TlsConnection`]; the URI scheme is client-controlled.
+    tls: bool,
+    /// Scheme and host reported by the nearest proxy, lowercased.
+    forwarded_proto: Option<String>,
+    forwarded_host: Option<String>,
+    /// `Host`, or the URI authority for HTTP/2.
+    host: Option<String>,
+    peer: Option<IpAddr>,
+    authorization: bool,
+    cookie_names: Vec<String>,
+}
+
+impl Transport {
+    fn from_request<B>(req: &Request<B>) -> Self {
+        let headers = req.headers();
+        Self {
+            tls: req.extensions().get::<TlsConnection>().is_some(),
+            forwarded_proto: forwarded_value(headers, "proto", "x-forwarded-proto"),
+            forwarded_host: forwarded_value(headers, "host", "x-forwarded-host"),
+            host: headers
+                .get(header::HOST)
+                .and_then(|value| value.to_str().ok())
+                .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+                .map(str::to_ascii_lowercase),
+            peer: req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+            authorization: headers.contains_key(header::AUTHORIZATION),
+            cookie_names: headers
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|pair| {
+                    pair.split_once('=')
+                        .map(|(name, _)| name.trim().to_string())
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The last hop's `param` from `Forwarded`, falling back to the matching
+/// `X-Forwarded-*` header, lowercased. Earlier entries were added before our
+/// proxy and may come from the client.
+fn forwarded_value(headers: &HeaderMap, param: &str, legacy: &'static str) -> Option<String> {
+    let last_entry = |name: HeaderName| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .last()
+            .map(str::trim)
+    };
+
+    let forwarded = last_entry(header::FORWARDED).and_then(|element| {
+        element.split(';').find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            key.eq_ignore_ascii_case(param)
+                .then(|| value.trim_matches('"').to_ascii_lowercase())
+        })
+    });
+    forwarded.or_else(|| last_entry(HeaderName::from_static(legacy)).map(str::to_ascii_lowercase))
+}
+
+/// What to do when the handler already set one of the security headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverrideMode {
+    /// Replace the handler's value with the configured one.
+    #[default]
+    Replace,
+    /// Only add headers the response does not have yet.
+    FillMissing,
+}
+
+/// Response extension that stops [`SecurityHeadersLayer`] from touching the
+/// listed headers, e.g. a relaxed `X-Frame-Options` on an embeddable widget:
+///
+/// ```ignore
+/// async fn widget() -> impl IntoResponse {
+///     (
+///         Extension(SkipSecurityHeaders::new([header::X_FRAME_OPTIONS])),
+///         [(header::X_FRAME_OPTIONS, "SAMEORIGIN")],
+///         Html(render_widget()),
+///     )
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SkipSecurityHeaders {
+    headers: Vec<HeaderName>,
+    all: bool,
+}
+
+impl SkipSecurityHeaders {
+    pub fn new(headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        Self {
+            headers: headers.into_iter().collect(),
+            all: false,
+        }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            headers: Vec::new(),
+            all: true,
+        }
+    }
+
+    fn skips(&self, name: &HeaderName) -> bool {
+        self.all || self.headers.contains(name)
+    }
+}
+
+/// Response extension asking [`SecurityHeadersLayer`] for `no-store` caching
+/// even on unauthenticated requests, e.g. a page showing a one-time token.
+/// A `Cache-Control` set by the handler still wins.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SensitiveResponse;
+
+/// Defaults for `Cross-Origin-Opener-Policy`, `-Embedder-Policy` and
+/// `-Resource-Policy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrossOriginPreset {
+    /// COOP `same-origin-allow-popups` and CORP `same-site`, no COEP, so
+    /// popup-based OAuth and payment flows keep working and sibling subdomains
+    /// can still embed this service's images and scripts.
+    #[default]
+    Default,
+    /// COOP and CORP `same-origin` plus COEP `require-corp`, which makes
+    /// `crossOriginIsolated` true and enables `SharedArrayBuffer` and
+    /// high-resolution timers for WASM pages. Popups lose their opener and
+    /// every cross-origin subresource must send CORP or CORS headers.
+    Isolated,
+}
+
+/// Per-request nonce for inline scripts and styles. Handlers read it with
+/// `Extension<CspNonce>` and render it into `nonce="..."` attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    pub fn generate() -> Self {
+        Self(BASE64.encode(rand::random::<[u8; 16]>()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CspNonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl SecurityHeadersConfig {
+    pub fn with_csp(mut self, policy: &ContentSecurityPolicy) -> Self {
+        self.content_security_policy = HeaderSetting::Value(policy.to_string());
+        self
+    }
+
+    pub fn with_csp_report_only(mut self, policy: &ContentSecurityPolicy) -> Self {
+        self.content_security_policy_report_only = Some(policy.to_string());
+        self
+    }
+
+    /// Switches to the [`CrossOriginPreset::Isolated`] values, keeping any
+    /// explicitly configured cross-origin header values.
+    pub fn cross_origin_isolated(mut self) -> Self {
+        self.cross_origin_preset = CrossOriginPreset::Isolated;
+        self
+    }
+
+    pub fn with_permissions_policy(mut self, policy: &PermissionsPolicy) -> Self {
+        self.permissions_policy = HeaderSetting::Value(policy.to_string());
+        self.permissions.clear();
+        self
+    }
+
+    /// Registers an inline script, typically an `include_str!` constant, by hash.
+    pub fn with_inline_script(mut self, content: impl AsRef<[u8]>) -> Self {
+        self.inline_script_hashes
+            .push(hash_token(&Source::sha256_of(content)));
+        self
+    }
+
+    pub fn with_inline_style(mut self, content: impl AsRef<[u8]>) -> Self {
+        self.inline_style_hashes
+            .push(hash_token(&Source::sha256_of(content)));
+        self
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, SecurityConfigError> {
+        let config = read_config_file(path.as_ref())?.security_headers;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, SecurityConfigError> {
+        let file: SecurityConfigFile = toml::from_str(contents)?;
+        file.security_headers.validate()?;
+        Ok(file.security_headers)
+    }
+
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        self.csp()?;
+        self.csp_report_only()?;
+        self.header_values()?;
+        self.https_header_values()?;
+        self.stripped_headers().map(drop)
+    }
+
+    pub fn into_layer(&self) -> Result<SecurityHeadersLayer, SecurityConfigError> {
+        Ok(SecurityHeadersLayer::from_prepared(
+            self.prepare()?,
+            Vec::new(),
+        ))
+    }
+
+    /// Request-level settings changed from their defaults, which
+    /// [`SecurityHeaderProfiles`] ignores in profiles.
+    fn request_settings(&self) -> Vec<&'static str> {
+        let default = Self::default();
+        [
+            (
+                "strict_transport_security",
+                self.strict_transport_security != default.strict_transport_security,
+            ),
+            ("hsts", self.hsts != default.hsts),
+            ("assume_https", self.assume_https != default.assume_https),
+            (
+                "trusted_proxies",
+                self.trusted_proxies != default.trusted_proxies,
+            ),
+            ("strip_headers", self.strip_headers != default.strip_headers),
+            (
+                "no_store_authenticated",
+                self.no_store_authenticated != default.no_store_authenticated,
+            ),
+            (
+                "session_cookies",
+                self.session_cookies != default.session_cookies,
+            ),
+            (
+                "reporting_endpoints",
+                self.reporting_endpoints != default.reporting_endpoints,
+            ),
+            ("nel", self.nel != default.nel),
+            ("override_mode", self.override_mode != default.override_mode),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+
+    fn prepare(&self) -> Result<PreparedHeaders, SecurityConfigError> {
+        let enforced = self.csp()?;
+        let report_only = self.csp_report_only()?;
+
+        if let (Some(enforced), Some(candidate)) = (&enforced, &report_only) {
+            let diff = enforced.diff(candidate);
+            tracing::info!(
+                added = ?diff.added,
+                removed = ?diff.removed,
+                "sending a report-only CSP candidate alongside the enforced policy"
+            );
+        }
+
+        // With nonces the CSP differs per request, so keep the parsed policy and
+        // build the header value in the response future instead.
+        let mut nonce_policies = Vec::new();
+        let mut headers = Vec::new();
+        let policies = [
+            (header::CONTENT_SECURITY_POLICY, enforced),
+            (header::CONTENT_SECURITY_POLICY_REPORT_ONLY, report_only),
+        ];
+        for (name, policy) in policies {
+            match policy {
+                Some(policy) if self.csp_nonce => nonce_policies.push((name, policy)),
+                Some(policy) => headers.push((name, policy.to_header_value()?)),
+                None => {}
+            }
+        }
+        headers.extend(self.header_values()?);
+
+        Ok(PreparedHeaders {
+            headers,
+            nonce_policies,
+            https_headers: self.https_header_values()?,
+            assume_https: self.assume_https,
+            trusted_proxies: self.trusted_proxies.clone(),
+            strip: self.stripped_headers()?,
+            no_store_authenticated: self.no_store_authenticated,
+            session_cookies: self.session_cookies.clone(),
+            override_mode: self.override_mode,
+            force_attachment: self.force_attachment,
+        })
+    }
+
+    /// Parses the enforced policy, so a malformed source fails at startup
+    /// instead of being ignored by browsers, and adds the inline hashes.
+    fn csp(&self) -> Result<Option<ContentSecurityPolicy>, SecurityConfigError> {
+        let Some(value) = self.content_security_policy.resolve(DEFAULT_CSP) else {
+            return Ok(None);
+        };
+        let policy = self.prepare_csp(&value)?;
+        if self.trusted_types_report_only {
+            Ok(Some(policy))
+        } else {
+            self.add_trusted_types(policy).map(Some)
+        }
+    }
+
+    fn csp_report_only(&self) -> Result<Option<ContentSecurityPolicy>, SecurityConfigError> {
+        let policy = self
+            .content_security_policy_report_only
+            .as_deref()
+            .map(|value| self.prepare_csp(value))
+            .transpose()?;
+        if !self.trusted_types_report_only || self.trusted_types.is_none() {
+            return Ok(policy);
+        }
+
+        // Without a configured candidate, report only on Trusted Types; a policy
+        // built through prepare_csp would also report every hashed-out script.
+        let policy = policy.unwrap_or_else(|| {
+            self.csp_report_uri
+                .iter()
+                .fold(ContentSecurityPolicy::new(), |policy, uri| {
+                    policy.report_uri(uri.clone())
+                })
+        });
+        self.add_trusted_types(policy).map(Some)
+    }
+
+    fn add_trusted_types(
+        &self,
+        policy: ContentSecurityPolicy,
+    ) -> Result<ContentSecurityPolicy, SecurityConfigError> {
+        let Some(names) = &self.trusted_types else {
+            return Ok(policy);
+        };
+        let policy = policy
+            .require_trusted_types(true)
+            .trusted_types(names.iter().cloned());
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    fn prepare_csp(&self, value: &str) -> Result<ContentSecurityPolicy, SecurityConfigError> {
+        let mut policy: ContentSecurityPolicy = value.parse()?;
+
+        for path in &self.inline_script_files {
+            policy = policy.allow_inline_script_file(path)?;
+        }
+        for path in &self.inline_style_files {
+            policy = policy.allow_inline_style_file(path)?;
+        }
+        for (directive, hashes) in [
+            (Directive::ScriptSrc, &self.inline_script_hashes),
+            (Directive::StyleSrc, &self.inline_style_hashes),
+        ] {
+            for hash in hashes {
+                let source: Source = format!("'{}'", hash.trim_matches('\'')).parse()?;
+                if !matches!(source, Source::Hash(..)) {
+                    return Err(SecurityConfigError::InvalidCsp {
+                        token: hash.clone(),
+                        reason: "expected a hash such as `sha256-…`",
+                    });
+                }
+                policy = policy.add_hash(directive, source);
+            }
+        }
+
+        if let Some(uri) = &self.csp_report_uri {
+            if !policy.report_uri.contains(uri) {
+                policy = policy.report_uri(uri.clone());
+            }
+        }
+
+        Ok(policy)
+    }
+
+    fn stripped_headers(&self) -> Result<Vec<HeaderName>, SecurityConfigError> {
+        match &self.strip_headers {
+            Some(names) => parse_header_names(names),
+            None => Ok(DEFAULT_STRIPPED_HEADERS
+                .into_iter()
+                .map(HeaderName::from_static)
+                .collect()),
+        }
+    }
+
+    fn header_values(&self) -> Result<Vec<(HeaderName, HeaderValue)>, SecurityConfigError> {
+        let settings = [
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                "x-content-type-options",
+                &self.x_content_type_options,
+                "nosniff",
+            ),
+            (
+                header::X_FRAME_OPTIONS,
+                "x-frame-options",
+                &self.x_frame_options,
+                "DENY",
+            ),
+            (
+                header::REFERRER_POLICY,
+                "referrer-policy",
+                &self.referrer_policy,
+                DEFAULT_REFERRER_POLICY,
+            ),
+        ];
+
+        let mut headers = Vec::new();
+        for (name, label, setting, default) in settings {
+            if let Some(value) = setting.resolve(default) {
+                headers.push((name, header_value(label, &value)?));
+            }
+        }
+        if let Some(policy) = self.permissions_policy()? {
+            headers.push((HeaderName::from_static("permissions-policy"), policy));
+        }
+        headers.extend(self.cross_origin_headers()?);
+        Ok(headers)
+    }
+
+    /// Headers browsers only honour over HTTPS, so they are left off plain-HTTP
+    /// responses where they would at best be ignored.
+    fn https_header_values(&self) -> Result<Vec<(HeaderName, HeaderValue)>, SecurityConfigError> {
+        let mut headers = Vec::new();
+        if let Some(endpoints) = self.reporting_endpoints()? {
+            headers.push((HeaderName::from_static("reporting-endpoints"), endpoints));
+        }
+        if let Some(value) = self
+            .strict_transport_security
+            .resolve(&self.hsts.to_string())
+        {
+            headers.push((
+                header::STRICT_TRANSPORT_SECURITY,
+                header_value("strict-transport-security", &value)?,
+            ));
+        }
+        if let Some(nel) = &self.nel {
+            let invalid = |token: &str, reason| SecurityConfigError::InvalidReporting {
+                token: token.to_string(),
+                reason,
+            };
+            let url = self
+                .reporting_endpoints
+                .get(&nel.report_to)
+                .ok_or_else(|| invalid(&nel.report_to, "not one of reporting_endpoints"))?;
+            if !url.starts_with("https://") {
+                return Err(invalid(url, "NEL endpoints must be absolute https:// URLs"));
+            }
+            for fraction in [nel.success_fraction, nel.failure_fraction] {
+                if !(0.0..=1.0).contains(&fraction) {
+                    return Err(invalid(
+                        &fraction.to_string(),
+                        "fractions must be within 0.0..=1.0",
+                    ));
+                }
+            }
+
+            let policy = serde_json::json!({
+                "report_to": nel.report_to,
+                "max_age": nel.max_age_secs,
+                "include_subdomains": nel.include_subdomains,
+                "success_fraction": nel.success_fraction,
+                "failure_fraction": nel.failure_fraction,
+            });
+            let group = serde_json::json!({
+                "group": nel.report_to,
+                "max_age": nel.max_age_secs,
+                "include_subdomains": nel.include_subdomains,
+                "endpoints": [{ "url": url }],
+            });
+            headers.push((
+                HeaderName::from_static("nel"),
+                header_value("nel", &policy.to_string())?,
+            ));
+            headers.push((
+                HeaderName::from_static("report-to"),
+                header_value("report-to", &group.to_string())?,
+            ));
+        }
+        Ok(headers)
+    }
+
+    fn reporting_endpoints(&self) -> Result<Option<HeaderValue>, SecurityConfigError> {
+        if self.reporting_endpoints.is_empty() {
+            return Ok(None);
+        }
+        let mut entries = Vec::new();
+        for (name, url) in &self.reporting_endpoints {
+            let reason = if name.is_empty()
+                || !name.starts_with(|c: char| c.is_ascii_lowercase())
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.*".contains(c))
+            {
+                Some("endpoint names must be lowercase tokens such as `csp-endpoint`")
+            } else if !(url.starts_with("https://") || url.starts_with('/'))
+                || url.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\')
+            {
+                Some("endpoint URLs must be https:// or a path")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                return Err(SecurityConfigError::InvalidReporting {
+                    token: format!("{name} = {url}"),
+                    reason,
+                });
+            }
+            entries.push(format!("{name}=\"{url}\""));
+        }
+        header_value("reporting-endpoints", &entries.join(", ")).map(Some)
+    }
+
+    fn cross_origin_headers(&self) -> Result<Vec<(HeaderName, HeaderValue)>, SecurityConfigError> {
+        let (opener_default, embedder_default, resource_default) = match self.cross_origin_preset {
+            CrossOriginPreset::Default => ("same-origin-allow-popups", None, "same-site"),
+            CrossOriginPreset::Isolated => ("same-origin", Some("require-corp"), "same-origin"),
+        };
+        let settings: [(&'static str, &HeaderSetting, Option<&str>, &[&str]); 3] = [
+            (
+                "cross-origin-opener-policy",
+                &self.cross_origin_opener_policy,
+                Some(opener_default),
+                &[
+                    "unsafe-none",
+                    "same-origin-allow-popups",
+                    "same-origin",
+                    "noopener-allow-popups",
+                ],
+            ),
+            (
+                "cross-origin-embedder-policy",
+                &self.cross_origin_embedder_policy,
+                embedder_default,
+                &["unsafe-none", "require-corp", "credentialless"],
+            ),
+            (
+                "cross-origin-resource-policy",
+                &self.cross_origin_resource_policy,
+                Some(resource_default),
+                &["same-site", "same-origin", "cross-origin"],
+            ),
+        ];
+
+        let mut headers = Vec::new();
+        for (name, setting, default, allowed) in settings {
+            let value = match (setting, default) {
+                (HeaderSetting::Enabled(false), _) | (HeaderSetting::Enabled(true), None) => {
+                    continue
+                }
+                (HeaderSetting::Enabled(true), Some(default)) => default.to_string(),
+                (HeaderSetting::Value(value), _) => value.trim().to_string(),
+            };
+            // Browsers ignore unknown values, which would silently drop the protection.
+            if !allowed.contains(&value.as_str()) {
+                return Err(SecurityConfigError::InvalidHeaderValue {
+                    header: name,
+                    value,
+                });
+            }
+            headers.push((HeaderName::from_static(name), header_value(name, &value)?));
+        }
+        Ok(headers)
+    }
+
+    fn permissions_policy(&self) -> Result<Option<HeaderValue>, SecurityConfigError> {
+        match &self.permissions_policy {
+            HeaderSetting::Enabled(false) => Ok(None),
+            HeaderSetting::Value(value) => header_value("permissions-policy", value).map(Some),
+            HeaderSetting::Enabled(true) => {
+                let mut policy = PermissionsPolicy::deny_all();
+                for (feature, allow) in &self.permissions {
+                    let allow = allow
+                        .iter()
+                        .map(|allow| allow.parse())
+                        .collect::<Result<Vec<Allow>, _>>()?;
+                    policy = policy.allow(feature.parse()?, allow);
+                }
+                policy.to_header_value().map(Some)
+            }
+        }
+    }
+}
+
+/// `sha256-…` without quotes, the form stored in [`SecurityHeadersConfig`].
+fn hash_token(source: &Source) -> String {
+    source.to_string().trim_matches('\'').to_string()
+}
+
+/// Collapses the line breaks and indentation of multi-line TOML strings.
+fn header_value(header: &'static str, value: &str) -> Result<HeaderValue, SecurityConfigError> {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    HeaderValue::from_str(&value)
+        .map_err(|_| SecurityConfigError::InvalidHeaderValue { header, value })
+}
+
+/// Header settings for one profile of [`SecurityHeaderProfiles`], selected by
+/// request path prefix or by response `Content-Type`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SecurityHeaderProfileConfig {
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// Media types such as `application/json` or `image/*`, without parameters.
+    #[serde(default)]
+    pub content_types: Vec<String>,
+    #[serde(flatten)]
+    pub headers: SecurityHeadersConfig,
+}
+
+impl SecurityHeaderProfileConfig {
+    /// JSON responses are never rendered, so they need no sources at all.
+    pub fn json_api() -> Self {
+        Self {
+            routes: Vec::new(),
+            content_types: vec!["application/json".into(), "application/problem+json".into()],
+            headers: SecurityHeadersConfig {
+                content_security_policy: HeaderSetting::Value(
+                    "default-src 'none'; frame-ancestors 'none'".into(),
+                ),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Sandboxed CSP for images, allowing the inline styles SVGs commonly use.
+    pub fn images() -> Self {
+        Self {
+            routes: Vec::new(),
+            content_types: vec!["image/*".into()],
+            headers: SecurityHeadersConfig {
+                content_security_policy: HeaderSetting::Value(
+                    "default-src 'none'; style-src 'unsafe-inline'; sandbox".into(),
+                ),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Generic binary responses are served as attachments in a sandbox.
+    pub fn downloads() -> Self {
+        Self {
+            routes: Vec::new(),
+            content_types: vec![
+                "application/octet-stream".into(),
+                "application/zip".into(),
+                "application/gzip".into(),
+                "application/x-tar".into(),
+            ],
+            headers: SecurityHeadersConfig {
+                content_security_policy: HeaderSetting::Value("default-src 'none'; sandbox".into()),
+                force_attachment: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Security headers that differ by route or response type, from
+/// `[security_header_profiles.<name>]` tables with `[security_headers]` as the
+/// fallback. A route match wins over a content-type match; among content-type
+/// matches the first profile by name wins.
+///
+/// Profiles only choose the response headers: CSP, frame, referrer,
+/// permissions and cross-origin policies and `force_attachment`. HSTS,
+/// `assume_https`, `trusted_proxies`, `strip_headers`, the no-store settings,
+/// reporting endpoints, NEL and `override_mode` always come from
+/// `[security_headers]`; setting them in a profile logs a warning.
+///
+/// ```toml
+/// [security_header_profiles.api]
+/// routes = ["/api"]
+/// content_types = ["application/json"]
+/// content_security_policy = "default-src 'none'; frame-ancestors 'none'"
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaderProfiles {
+    pub default: SecurityHeadersConfig,
+    pub profiles: BTreeMap<String, SecurityHeaderProfileConfig>,
+}
+
+impl SecurityHeaderProfiles {
+    pub fn new(default: SecurityHeadersConfig) -> Self {
+        Self {
+            default,
+            profiles: BTreeMap::new(),
+        }
+    }
+
+    /// `default` for HTML plus the JSON, image and download presets.
+    pub fn recommended(default: SecurityHeadersConfig) -> Self {
+        Self::new(default)
+            .with_profile("json", SecurityHeaderProfileConfig::json_api())
+            .with_profile("images", SecurityHeaderProfileConfig::images())
+            .with_profile("downloads", SecurityHeaderProfileConfig::downloads())
+    }
+
+    pub fn with_profile(
+        mut self,
+        name: impl Into<String>,
+        profile: SecurityHeaderProfileConfig,
+    ) -> Self {
+        self.profiles.insert(name.into(), profile);
+        self
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, SecurityConfigError> {
+        Self::from_file(read_config_file(path.as_ref())?)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, SecurityConfigError> {
+        Self::from_file(toml::from_str(contents)?)
+    }
+
+    fn from_file(file: SecurityConfigFile) -> Result<Self, SecurityConfigError> {
+        let profiles = Self {
+            default: file.security_headers,
+            profiles: file.security_header_profiles,
+        };
+        profiles.validate()?;
+        Ok(profiles)
+    }
+
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        self.into_layer().map(drop)
+    }
+
+    pub fn into_layer(&self) -> Result<SecurityHeadersLayer, SecurityConfigError> {
+        let default = self.default.prepare()?;
+        let mut profiles = Vec::new();
+        for (name, profile) in &self.profiles {
+            let mut headers =
+                profile
+                    .headers
+                    .prepare()
+                    .map_err(|source| SecurityConfigError::HeaderProfile {
+                        profile: name.clone(),
+                        source: Box::new(source),
+                    })?;
+            let ignored = profile.headers.request_settings();
+            if !ignored.is_empty() {
+                tracing::warn!(
+                    profile = %name,
+                    settings = ?ignored,
+                    "security header profile sets request-level settings; \
+                     the values from [security_headers] are used instead"
+                );
+            }
+            headers.inherit_request_settings(&default);
+            profiles.push(PreparedProfile {
+                routes: profile.routes.clone(),
+                content_types: profile
+                    .content_types
+                    .iter()
+                    .map(|content_type| content_type.trim().to_ascii_lowercase())
+                    .collect(),
+                headers: Arc::new(headers),
+            });
+        }
+        Ok(SecurityHeadersLayer::from_prepared(default, profiles))
+    }
+}
+
+/// Adds the configured security headers to every response. Header values are
+/// built once when the layer is created; only a nonce-bearing CSP is rendered
+/// per request.
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersLayer {
+    default: Arc<PreparedHeaders>,
+    profiles: Arc<Vec<PreparedProfile>>,
+    needs_nonce: bool,
+}
+
+#[derive(Debug, Clone)]
+struct PreparedHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+    /// Enforced and report-only policies that need the request's nonce.
+    nonce_policies: Vec<(HeaderName, ContentSecurityPolicy)>,
+    /// HSTS, NEL and `Reporting-Endpoints`, kept apart from `headers` because
+    /// browsers only honour them over HTTPS.
+    https_headers: Vec<(HeaderName, HeaderValue)>,
+    assume_https: bool,
+    trusted_proxies: TrustedProxies,
+    strip: Vec<HeaderName>,
+    no_store_authenticated: bool,
+    session_cookies: Option<Vec<String>>,
+    override_mode: OverrideMode,
+    force_attachment: bool,
+}
+
+/// Per-request state the response side needs.
+#[derive(Debug, Default)]
+struct RequestContext {
+    route_profile: Option<Arc<PreparedHeaders>>,
+    nonce: Option<CspNonce>,
+    transport: Transport,
+}
+
+#[derive(Debug)]
+struct PreparedProfile {
+    routes: Vec<String>,
+    content_types: Vec<String>,
+    headers: Arc<PreparedHeaders>,
+}
+
+impl SecurityHeadersLayer {
+    pub fn new(config: &SecurityHeadersConfig) -> Result<Self, SecurityConfigError> {
+        config.into_layer()
+    }
+
+    fn from_prepared(default: PreparedHeaders, profiles: Vec<PreparedProfile>) -> Self {
+        let needs_nonce = !default.nonce_policies.is_empty()
+            || profiles
+                .iter()
+                .any(|profile| !profile.headers.nonce_policies.is_empty());
+        Self {
+            default: Arc::new(default),
+            profiles: Arc::new(profiles),
+            needs_nonce,
+        }
+    }
+
+    fn for_route(&self, path: &str) -> Option<Arc<PreparedHeaders>> {
+        longest_route(
+            self.profiles.iter().flat_map(|profile| {
+                profile
+                    .routes
+                    .iter()
+                    .map(move |route| (route.as_str(), profile))
+            }),
+            path,
+        )
+        .map(|profile| Arc::clone(&profile.headers))
+    }
+
+    fn for_content_type(&self, headers: &HeaderMap) -> &PreparedHeaders {
+        let Some(essence) = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase())
+        else {
+            return &self.default;
+        };
+
+        self.profiles
+            .iter()
+            .find(|profile| {
+                profile
+                    .content_types
+                    .iter()
+                    .any(|pattern| match pattern.strip_suffix("/*") {
+                        Some(kind) => essence
+                            .split_once('/')
+                            .is_some_and(|(essence_kind, _)| essence_kind == kind),
+                        None => *pattern == essence,
+                    })
+            })
+            .map_or(&self.default, |profile| &profile.headers)
+    }
+
+    fn apply<B>(&self, context: &RequestContext, response: &mut Response<B>) {
+        let prepared = context
+            .route_profile
+            .as_deref()
+            .unwrap_or_else(|| self.for_content_type(response.headers()));
+        prepared.apply(context, response);
+    }
+}
+
+impl PreparedHeaders {
+    /// Settings that depend on the request or the site rather than the response
+    /// type come from `[security_headers]`, so a profile cannot drop them.
+    fn inherit_request_settings(&mut self, default: &PreparedHeaders) {
+        self.https_headers = default.https_headers.clone();
+        self.assume_https = default.assume_https;
+        self.trusted_proxies = default.trusted_proxies.clone();
+        self.strip = default.strip.clone();
+        self.no_store_authenticated = default.no_store_authenticated;
+        self.session_cookies = default.session_cookies.clone();
+        self.override_mode = default.override_mode;
+    }
+
+    fn is_authenticated(&self, transport: &Transport) -> bool {
+        transport.authorization
+            || match &self.session_cookies {
+                Some(names) => transport
+                    .cookie_names
+                    .iter()
+                    .any(|cookie| names.contains(cookie)),
+                None => !transport.cookie_names.is_empty(),
+            }
+    }
+
+    fn apply<B>(&self, context: &RequestContext, response: &mut Response<B>) {
+        let skip = response.extensions_mut().remove::<SkipSecurityHeaders>();
+        let sensitive = response
+            .extensions_mut()
+            .remove::<SensitiveResponse>()
+            .is_some()
+            || (self.no_store_authenticated && self.is_authenticated(&context.transport));
+        let headers = response.headers_mut();
+        let wanted = |headers: &HeaderMap, name: &HeaderName| {
+            let skipped = skip.as_ref().is_some_and(|skip| skip.skips(name));
+            let keep_existing =
+                self.override_mode == OverrideMode::FillMissing && headers.contains_key(name);
+            !skipped && !keep_existing
+        };
+
+        for name in &self.strip {
+            if !skip.as_ref().is_some_and(|skip| skip.skips(name)) {
+                headers.remove(name);
+            }
+        }
+
+        for (name, value) in &self.headers {
+            if wanted(headers, name) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+
+        if self.assume_https || self.trusted_proxies.is_https_transport(&context.transport) {
+            for (name, value) in &self.https_headers {
+                if wanted(headers, name) {
+                    headers.insert(name.clone(), value.clone());
+                }
+            }
+        }
+
+        if let Some(nonce) = &context.nonce {
+            for (name, policy) in &self.nonce_policies {
+                if !wanted(headers, name) {
+                    continue;
+                }
+                if let Ok(value) = HeaderValue::from_str(&policy.with_nonce(nonce).to_string()) {
+                    headers.insert(name.clone(), value);
+                }
+            }
+        }
+
+        // Any handler-set Cache-Control is a deliberate caching policy, even a
+        // permissive one, so only fill in no-store when there is none.
+        let skipped = skip
+            .as_ref()
+            .is_some_and(|skip| skip.skips(&header::CACHE_CONTROL));
+        if sensitive && !skipped && !headers.contains_key(header::CACHE_CONTROL) {
+            headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+        }
 
-pub fn cors_layer() -> CorsLayer {
-    CorsLayer::new()
-        .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-        .allow_methods([
-            http::Method::GET,
-            http::Method::POST,
-            http::Method::PUT,
-            http::Method::DELETE,
-        ])
-        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
-        .allow_credentials(true)
+        // A handler-supplied disposition carries the filename, so never replace it.
+        if self.force_attachment && !headers.contains_key(header::CONTENT_DISPOSITION) {
+            headers.insert(
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_static("attachment"),
+            );
+        }
+    }
 }
 
+impl<S> Layer<S> for SecurityHeadersLayer {
+    type Service = SecurityHeaders<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SecurityHeaders {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders<S> {
+    inner: S,
+    layer: SecurityHeadersLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for SecurityHeaders<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = SecurityHeadersFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let nonce = self.layer.needs_nonce.then(CspNonce::generate);
+        if let Some(nonce) = &nonce {
+            req.extensions_mut().insert(nonce.clone());
+        }
+        let context = RequestContext {
+            route_profile: self.layer.for_route(req.uri().path()),
+            nonce,
+            transport: Transport::from_request(&req),
+        };
+
+        SecurityHeadersFuture {
+            inner: self.inner.call(req),
+            layer: self.layer.clone(),
+            context,
+        }
+    }
+}
+
+pin_project! {
+    pub struct SecurityHeadersFuture<F> {
+        #[pin]
+        inner: F,
+        layer: SecurityHeadersLayer,
+        context: RequestContext,
+    }
+}
+
+impl<F, ResBody, E> Future for SecurityHeadersFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+{
+    type Output = Result<Response<ResBody>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = std::task::ready!(this.inner.poll(cx))?;
+        this.layer.apply(this.context, &mut response);
+        Poll::Ready(Ok(response))
+    }
+}
+
+/// Function middleware with the default header set, for use with
+/// `middleware::from_fn`. Prefer [`SecurityHeadersLayer`] when any header needs
+/// to change.
+///
+/// HSTS is only sent when the request is known to be HTTPS, i.e. it carries
+/// [`TlsConnection`]. Behind a TLS-terminating proxy this middleware never sends
+/// HSTS; use [`SecurityHeadersLayer`] with `trusted_proxies` there.
 pub async fn security_headers<B>(
     req: Request<B>,
     next: Next<B>,
 ) -> Result<Response, StatusCode> {
+    static DEFAULT: OnceLock<SecurityHeadersLayer> = OnceLock::new();
+
+    let context = RequestContext {
+        transport: Transport::from_request(&req),
+        ..Default::default()
+    };
     let mut response = next.run(req).await;
-    let headers = response.headers_mut();
-
-    // CSP
-    headers.insert(
-        header::CONTENT_SECURITY_POLICY,
-        HeaderValue::from_static(
-            "default-src 'self'; \
-             script-src 'self'; \
-             style-src 'self' 'unsafe-inline'; \
-             img-src 'self' data: https:; \
-             font-src 'self'; \
-             connect-src 'self'; \
-             frame-ancestors 'none';"
-        ),
-    );
-
-    // HSTS
-    headers.insert(
-        header::STRICT_TRANSPORT_SECURITY,
-        HeaderValue::from_static("max-age=31536000; includeSubDomains; preload"),
-    );
-
-    // Other headers
-    headers.insert(
-        header::X_CONTENT_TYPE_OPTIONS,
-        HeaderValue::from_static("nosniff"),
-    );
-
-    headers.insert(
-        header::X_FRAME_OPTIONS,
-        HeaderValue::from_static("DENY"),
-    );
-
-    headers.insert(
-        "Referrer-Policy",
-        HeaderValue::from_static("strict-origin-when-cross-origin"),
-    );
+    DEFAULT
+        .get_or_init(|| {
+            SecurityHeadersConfig::default()
+                .into_layer()
+                .expect("default security headers are valid")
+        })
+        .apply(&context, &mut response);
 
     Ok(response)
 }
+
+/// Browser state that `Clear-Site-Data` can remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum SiteData {
+    #[serde(rename = "cache")]
+    Cache,
+    #[serde(rename = "cookies")]
+    Cookies,
+    /// `localStorage`, IndexedDB, service workers and other script storage.
+    #[serde(rename = "storage")]
+    Storage,
+    /// Reloads open documents of the origin.
+    #[serde(rename = "executionContexts")]
+    ExecutionContexts,
+    #[serde(rename = "clientHints")]
+    ClientHints,
+    /// Everything above, including types added to browsers later.
+    #[serde(rename = "*")]
+    All,
+}
+
+impl SiteData {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cache => "cache",
+            Self::Cookies => "cookies",
+            Self::Storage => "storage",
+            Self::ExecutionContexts => "executionContexts",
+            Self::ClientHints => "clientHints",
+            Self::All => "*",
+        }
+    }
+}
+
+/// `Clear-Site-Data` for logout and account deletion, usable as a response part
+/// or as a route layer. Browsers only honour it over HTTPS.
+///
+/// ```ignore
+/// async fn logout(session: Session) -> impl IntoResponse {
+///     session.destroy().await;
+///     (ClearSiteData::logout(), Redirect::to("/"))
+/// }
+///
+/// let app = Router::new()
+///     .route("/account", delete(delete_account))
+///     .route_layer(ClearSiteData::new([SiteData::All]).into_layer());
+/// ```
+///
+/// The header is independent of [`SecurityHeadersLayer`], which neither strips
+/// nor overrides it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClearSiteData {
+    types: Vec<SiteData>,
+}
+
+impl ClearSiteData {
+    /// An empty list sends no header.
+    pub fn new(types: impl IntoIterator<Item = SiteData>) -> Self {
+        let mut deduped = Vec::new();
+        for site_data in types {
+            if !deduped.contains(&site_data) {
+                deduped.push(site_data);
+            }
+        }
+        Self { types: deduped }
+    }
+
+    /// Cookies, storage and cache: everything a signed-out user should not
+    /// leave behind, without reloading other open tabs.
+    pub fn logout() -> Self {
+        Self::new([SiteData::Cache, SiteData::Cookies, SiteData::Storage])
+    }
+
+    pub fn types(&self) -> &[SiteData] {
+        &self.types
+    }
+
+    /// `"cache", "cookies"`: each type is a quoted string.
+    pub fn header_value(&self) -> Option<HeaderValue> {
+        if self.types.is_empty() {
+            return None;
+        }
+        let value = self
+            .types
+            .iter()
+            .map(|site_data| format!("\"{}\"", site_data.name()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(HeaderValue::from_str(&value).expect("site data names are valid header text"))
+    }
+
+    /// Adds the header to successful and redirect responses of the wrapped
+    /// routes. Errors are left alone so a failed logout keeps the session.
+    pub fn into_layer(&self) -> ClearSiteDataLayer {
+        ClearSiteDataLayer {
+            value: self.header_value(),
+        }
+    }
+}
+
+impl IntoResponseParts for ClearSiteData {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Some(value) = self.header_value() {
+            res.headers_mut().insert(CLEAR_SITE_DATA, value);
+        }
+        Ok(res)
+    }
+}
+
+const CLEAR_SITE_DATA: HeaderName = HeaderName::from_static("clear-site-data");
+
+#[derive(Debug, Clone)]
+pub struct ClearSiteDataLayer {
+    value: Option<HeaderValue>,
+}
+
+impl<S> Layer<S> for ClearSiteDataLayer {
+    type Service = ClearSiteDataService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClearSiteDataService {
+            inner,
+            value: self.value.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClearSiteDataService<S> {
+    inner: S,
+    value: Option<HeaderValue>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for ClearSiteDataService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = ClearSiteDataFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        ClearSiteDataFuture {
+            inner: self.inner.call(req),
+            value: self.value.clone(),
+        }
+    }
+}
+
+pin_project! {
+    pub struct ClearSiteDataFuture<F> {
+        #[pin]
+        inner: F,
+        value: Option<HeaderValue>,
+    }
+}
+
+impl<F, ResBody, E> Future for ClearSiteDataFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+{
+    type Output = Result<Response<ResBody>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut response = std::task::ready!(this.inner.poll(cx))?;
+        let status = response.status();
+        if let Some(value) = this.value.take() {
+            if status.is_success() || status.is_redirection() {
+                // A handler that already chose what to clear knows better.
+                response
+                    .headers_mut()
+                    .entry(CLEAR_SITE_DATA)
+                    .or_insert(value);
+            }
+        }
+        Poll::Ready(Ok(response))
+    }
+}
+
+/// Redirects plain-HTTP requests to HTTPS, loaded from the `[https_redirect]`
+/// table. Only hosts in `allowed_hosts` are redirected; anything else gets
+/// `400 Bad Request`, so a forged `Host` cannot turn this into an open redirect.
+///
+/// ```toml
+/// [https_redirect]
+/// allowed_hosts = ["example.com", "*.example.com"]
+/// exempt_paths = ["/healthz"]
+///
+/// [https_redirect.trusted_proxies]
+/// addresses = ["10.0.0.2"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HttpsRedirectConfig {
+    /// Exact host names, or `*.example.com` for any subdomain. Ports are ignored.
+    pub allowed_hosts: Vec<String>,
+    /// Port for the `Location` URL; 443 is left out.
+    pub https_port: u16,
+    /// Path prefixes served over HTTP as well, e.g. load balancer health checks.
+    pub exempt_paths: Vec<String>,
+    pub status: RedirectStatus,
+    /// Proxies whose `Forwarded`/`X-Forwarded-Proto` mark a request as already
+    /// HTTPS, and whose forwarded host replaces `Host`.
+    pub trusted_proxies: TrustedProxies,
+}
+
+impl Default for HttpsRedirectConfig {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            https_port: 443,
+            exempt_paths: vec!["/health".into(), "/healthz".into(), "/readyz".into()],
+            status: RedirectStatus::default(),
+            trusted_proxies: TrustedProxies::default(),
+        }
+    }
+}
+
+/// Status code for the redirect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectStatus {
+    /// `301` for `GET`/`HEAD` and `308` otherwise, since clients turn a 301
+    /// `POST` into a `GET` and drop the body.
+    #[default]
+    Auto,
+    MovedPermanently,
+    PermanentRedirect,
+}
+
+impl HttpsRedirectConfig {
+    pub fn new(allowed_hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            allowed_hosts: allowed_hosts.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, SecurityConfigError> {
+        let config = read_config_file(path.as_ref())?.https_redirect;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, SecurityConfigError> {
+        let file: SecurityConfigFile = toml::from_str(contents)?;
+        file.https_redirect.validate()?;
+        Ok(file.https_redirect)
+    }
+
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        if self.allowed_hosts.is_empty() {
+            return Err(SecurityConfigError::NoRedirectHosts);
+        }
+        for host in &self.allowed_hosts {
+            let name = host.strip_prefix("*.").unwrap_or(host);
+            let reason = if name.is_empty() {
+                Some("host is empty")
+            } else if name.contains('*') {
+                Some("wildcards are only allowed as a leading `*.`")
+            } else if name.contains([':', '/', '@']) || name.contains(char::is_whitespace) {
+                Some("expected a bare host name without scheme, port or path")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                return Err(SecurityConfigError::InvalidRedirectHost {
+                    host: host.clone(),
+                    reason,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_layer(&self) -> Result<HttpsRedirectLayer, SecurityConfigError> {
+        self.validate()?;
+        let mut config = self.clone();
+        for host in &mut config.allowed_hosts {
+            *host = host.to_ascii_lowercase();
+        }
+        Ok(HttpsRedirectLayer {
+            config: Arc::new(config),
+        })
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix("*.") {
+                Some(suffix) => host
+                    .strip_suffix(suffix)
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                None => allowed == host,
+            })
+    }
+
+    /// `Location` for a plain-HTTP request, or `None` when it should pass
+    /// through. `Err` means the host is not one of ours.
+    fn redirect_target<B>(&self, req: &Request<B>) -> Result<Option<String>, ()> {
+        let path = req.uri().path();
+        if self
+            .exempt_paths
+            .iter()
+            .any(|exempt| route_contains(exempt, path))
+        {
+            return Ok(None);
+        }
+
+        let transport = Transport::from_request(req);
+        if self.trusted_proxies.is_https_transport(&transport) {
+            return Ok(None);
+        }
+
+        let host = self
+            .trusted_proxies
+            .original_host(&transport)
+            .map(strip_port)
+            .filter(|host| self.allows_host(host))
+            .ok_or(())?;
+        let port = match self.https_port {
+            443 => String::new(),
+            port => format!(":{port}"),
+        };
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str());
+        Ok(Some(format!("https://{host}{port}{path_and_query}")))
+    }
+}
+
+/// `example.com:8080` → `example.com`, `[::1]:8080` → `[::1]`.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => &host[..colon],
+        _ => host,
+    }
+}
+
+/// Tower layer built by [`HttpsRedirectConfig::into_layer`]. Put it outermost so
+/// plain-HTTP requests never reach the handlers.
+///
+/// Requests count as HTTPS only per [`TrustedProxies::is_https`]. When the same
+/// router is also served on a TLS listener, add [`TlsConnection`] there; without
+/// it HTTP/1.1 requests on that listener look like plain HTTP and are
+/// redirected to themselves.
+#[derive(Debug, Clone)]
+pub struct HttpsRedirectLayer {
+    config: Arc<HttpsRedirectConfig>,
+}
+
+impl<S> Layer<S> for HttpsRedirectLayer {
+    type Service = HttpsRedirect<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpsRedirect {
+            inner,
+            config: Arc::clone(&self.config),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpsRedirect<S> {
+    inner: S,
+    config: Arc<HttpsRedirectConfig>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HttpsRedirect<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = HttpsRedirectFuture<S::Future, ResBody>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let location = match self.config.redirect_target(&req) {
+            Ok(None) => {
+                return HttpsRedirectFuture::Inner {
+                    future: self.inner.call(req),
+                }
+            }
+            Ok(Some(location)) => HeaderValue::from_str(&location).ok(),
+            Err(()) => None,
+        };
+        let Some(location) = location else {
+            tracing::debug!(
+                host = ?req.headers().get(header::HOST),
+                "refusing HTTPS redirect for a host that is not allowed"
+            );
+            return HttpsRedirectFuture::ready(StatusCode::BAD_REQUEST, None);
+        };
+
+        let status = match self.config.status {
+            RedirectStatus::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            RedirectStatus::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+            RedirectStatus::Auto if matches!(*req.method(), Method::GET | Method::HEAD) => {
+                StatusCode::MOVED_PERMANENTLY
+            }
+            RedirectStatus::Auto => StatusCode::PERMANENT_REDIRECT,
+        };
+        HttpsRedirectFuture::ready(status, Some(location))
+    }
+}
+
+pin_project! {
+    #[project = HttpsRedirectFutureProj]
+    pub enum HttpsRedirectFuture<F, B> {
+        Inner {
+            #[pin]
+            future: F,
+        },
+        Ready {
+            response: Option<Response<B>>,
+        },
+    }
+}
+
+impl<F, B: Default> HttpsRedirectFuture<F, B> {
+    fn ready(status: StatusCode, location: Option<HeaderValue>) -> Self {
+        let mut response = Response::new(B::default());
+        *response.status_mut() = status;
+        if let Some(location) = location {
+            response.headers_mut().insert(header::LOCATION, location);
+        }
+        Self::Ready {
+            response: Some(response),
+        }
+    }
+}
+
+impl<F, B, E> Future for HttpsRedirectFuture<F, B>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<B>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            HttpsRedirectFutureProj::Inner { future } => future.poll(cx),
+            HttpsRedirectFutureProj::Ready { response } => Poll::Ready(Ok(response
+                .take()
+                .expect("HttpsRedirectFuture polled after completion"))),
+        }
+    }
+}
+
+/// Largest report body accepted by [`CspReportCollector`] unless overridden.
+const MAX_CSP_REPORT_BYTES: usize = 16 * 1024;
+/// Longest directive, URI or document URL kept from a report.
+const MAX_REPORT_FIELD_LEN: usize = 2048;
+
+/// Receives CSP violation reports and counts them by directive and blocked URI.
+///
+/// Accepts the legacy `application/csp-report` body sent for `report-uri` and the
+/// Reporting API `application/reports+json` batches sent for `report-to`.
+///
+/// ```ignore
+/// let reports = CspReportCollector::new();
+/// let app = Router::new()
+///     .merge(reports.routes("/csp-report"))
+///     .merge(reports.summary_routes("/admin/csp-report").route_layer(require_admin))
+///     .layer(
+///         SecurityHeadersConfig {
+///             csp_report_uri: Some("/csp-report".into()),
+///             ..Default::default()
+///         }
+///         .into_layer()?,
+///     );
+/// ```
+#[derive(Clone)]
+pub struct CspReportCollector {
+    inner: Arc<CspReportCollectorInner>,
+}
+
+struct CspReportCollectorInner {
+    max_body_bytes: usize,
+    max_groups: usize,
+    state: Mutex<CspReportState>,
+}
+
+#[derive(Default)]
+struct CspReportState {
+    total: u64,
+    dropped: u64,
+    groups: HashMap<(String, String), CspViolationGroup>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CspViolationGroup {
+    pub directive: String,
+    pub blocked_uri: String,
+    pub count: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub last_document_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CspReportSummary {
+    pub total_reports: u64,
+    /// Reports that were valid but arrived after `max_groups` was reached.
+    pub dropped_reports: u64,
+    pub groups: Vec<CspViolationGroup>,
+}
+
+#[derive(Deserialize)]
+struct LegacyCspReport {
+    #[serde(rename = "csp-report")]
+    report: LegacyCspReportBody,
+}
+
+#[derive(Deserialize)]
+struct LegacyCspReportBody {
+    #[serde(rename = "document-uri", default)]
+    document_uri: String,
+    #[serde(rename = "violated-directive", default)]
+    violated_directive: String,
+    #[serde(rename = "effective-directive", default)]
+    effective_directive: String,
+    #[serde(rename = "blocked-uri", default)]
+    blocked_uri: String,
+}
+
+#[derive(Deserialize)]
+struct ReportingApiReport {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    body: Option<ReportingApiCspBody>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportingApiCspBody {
+    #[serde(default, rename = "documentURL")]
+    document_url: String,
+    #[serde(default)]
+    effective_directive: String,
+    #[serde(default, rename = "blockedURL")]
+    blocked_url: String,
+}
+
+struct CspViolation {
+    directive: String,
+    blocked_uri: String,
+    document_uri: String,
+}
+
+impl Default for CspReportCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CspReportCollector {
+    pub fn new() -> Self {
+        Self::with_limits(MAX_CSP_REPORT_BYTES, 1000)
+    }
+
+    /// `max_groups` caps the distinct directive/URI pairs kept in memory.
+    pub fn with_limits(max_body_bytes: usize, max_groups: usize) -> Self {
+        Self {
+            inner: Arc::new(CspReportCollectorInner {
+                max_body_bytes,
+                max_groups,
+                state: Mutex::new(CspReportState::default()),
+            }),
+        }
+    }
+
+    /// `POST {path}` receives reports from browsers.
+    pub fn routes<S>(&self, path: &str) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Router::new()
+            .route(path, post(receive_csp_report))
+            // Stop reading oversized bodies early; the handler re-checks the length.
+            .layer(DefaultBodyLimit::max(self.inner.max_body_bytes))
+            .with_state(self.clone())
+    }
+
+    /// `GET {path}` returns the [`CspReportSummary`]. Kept apart from
+    /// [`routes`](Self::routes) because the document and blocked URIs reveal
+    /// which pages visitors opened; mount it behind authentication.
+    pub fn summary_routes<S>(&self, path: &str) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Router::new()
+            .route(path, get(csp_report_summary))
+            .with_state(self.clone())
+    }
+
+    pub fn summary(&self) -> CspReportSummary {
+        let state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut groups: Vec<_> = state.groups.values().cloned().collect();
+        groups.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.directive.cmp(&b.directive))
+        });
+        CspReportSummary {
+            total_reports: state.total,
+            dropped_reports: state.dropped,
+            groups,
+        }
+    }
+
+    fn record(&self, violations: Vec<CspViolation>) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let mut state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        for violation in violations {
+            state.total += 1;
+            let key = (violation.directive.clone(), violation.blocked_uri.clone());
+            if !state.groups.contains_key(&key) && state.groups.len() >= self.inner.max_groups {
+                state.dropped += 1;
+                continue;
+            }
+            let group = state
+                .groups
+                .entry(key)
+                .or_insert_with(|| CspViolationGroup {
+                    directive: violation.directive,
+                    blocked_uri: violation.blocked_uri,
+                    count: 0,
+                    first_seen: now,
+                    last_seen: now,
+                    last_document_uri: String::new(),
+                });
+            group.count += 1;
+            group.last_seen = now;
+            group.last_document_uri = violation.document_uri;
+        }
+    }
+}
+
+fn parse_csp_reports(content_type: &str, body: &[u8]) -> Option<Vec<CspViolation>> {
+    let violations = match content_type {
+        "application/csp-report" | "application/json" => {
+            let LegacyCspReport { report } = serde_json::from_slice(body).ok()?;
+            let directive = if report.effective_directive.is_empty() {
+                // Older browsers only send the full violated directive.
+                report
+                    .violated_directive
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            } else {
+                report.effective_directive
+            };
+            vec![CspViolation {
+                directive,
+                blocked_uri: report.blocked_uri,
+                document_uri: report.document_uri,
+            }]
+        }
+        "application/reports+json" => {
+            let reports: Vec<ReportingApiReport> = serde_json::from_slice(body).ok()?;
+            reports
+                .into_iter()
+                .filter(|report| report.kind == "csp-violation")
+                .filter_map(|report| report.body)
+                .map(|body| CspViolation {
+                    directive: body.effective_directive,
+                    blocked_uri: body.blocked_url,
+                    document_uri: body.document_url,
+                })
+                .collect()
+        }
+        _ => return None,
+    };
+
+    violations
+        .into_iter()
+        .map(|violation| {
+            let directive = violation.directive.trim().to_ascii_lowercase();
+            let valid = !directive.is_empty()
+                && directive.len() <= 64
+                && directive
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c == '-');
+            valid.then(|| CspViolation {
+                directive,
+                blocked_uri: normalize_report_uri(&violation.blocked_uri),
+                document_uri: normalize_report_uri(&violation.document_uri),
+            })
+        })
+        .collect()
+}
+
+/// Drops query strings and fragments, which often carry tokens and would give
+/// every page view its own group, and caps the length.
+fn normalize_report_uri(uri: &str) -> String {
+    let uri = uri.split(['?', '#']).next().unwrap_or_default().trim();
+    let uri = if uri.is_empty() { "unknown" } else { uri };
+    let mut end = uri.len().min(MAX_REPORT_FIELD_LEN);
+    while !uri.is_char_boundary(end) {
+        end -= 1;
+    }
+    uri[..end].to_string()
+}
+
+pub async fn receive_csp_report(
+    State(collector): State<CspReportCollector>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if body.len() > collector.inner.max_body_bytes {
+        return StatusCode::PAYLOAD_TOO_LARGE;
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match parse_csp_reports(&content_type, &body) {
+        Some(violations) => {
+            collector.record(violations);
+            StatusCode::NO_CONTENT
+        }
+        None if !matches!(
+            content_type.as_str(),
+            "application/csp-report" | "application/json" | "application/reports+json"
+        ) =>
+        {
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        }
+        None => StatusCode::BAD_REQUEST,
+    }
+}
+
+pub async fn csp_report_summary(
+    State(collector): State<CspReportCollector>,
+) -> Json<CspReportSummary> {
+    Json(collector.summary())
+}
+
+/// Largest batch accepted by [`NetworkReportCollector`] unless overridden.
+const MAX_NETWORK_REPORT_BYTES: usize = 64 * 1024;
+/// Distinct report types counted before the rest go to [`OTHER_REPORT_TYPE`].
+const MAX_REPORT_TYPES: usize = 32;
+const OTHER_REPORT_TYPE: &str = "other";
+
+/// Where [`NetworkReportCollector`] keeps reports. Both are bounded, so a flood
+/// of reports costs at most the configured memory or disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportSink {
+    /// Keeps the newest `capacity` reports.
+    Memory { capacity: usize },
+    /// Appends one JSON object per line until the file reaches `max_bytes`.
+    File { path: PathBuf, max_bytes: u64 },
+}
+
+/// Receives Reporting API batches (`application/reports+json`) such as NEL
+/// `network-error`, `deprecation` and `intervention` reports.
+///
+/// ```ignore
+/// let reports = NetworkReportCollector::to_file("/var/log/app/reports.jsonl", 50 << 20)?;
+/// let app = Router::new()
+///     .merge(reports.routes("/reports"))
+///     .merge(reports.summary_routes("/admin/reports").route_layer(require_admin));
+/// ```
+///
+/// Query strings and fragments are dropped from every URL in a report, since
+/// they often carry tokens.
+///
+/// NEL reports are sent when the site itself fails, so point the NEL endpoint
+/// at a collector on a different host than the one it monitors.
+/// Browsers send reports cross-origin with a CORS preflight, so that host
+/// needs a [`CorsConfig`] allowing `POST` and `Content-Type` from the sites.
+#[derive(Clone)]
+pub struct NetworkReportCollector {
+    max_body_bytes: usize,
+    inner: Arc<NetworkReportCollectorInner>,
+}
+
+struct NetworkReportCollectorInner {
+    sink: ReportSink,
+    state: Mutex<NetworkReportState>,
+}
+
+#[derive(Default)]
+struct NetworkReportState {
+    total: u64,
+    dropped: u64,
+    by_type: BTreeMap<String, u64>,
+    recent: VecDeque<StoredReport>,
+    file: Option<std::fs::File>,
+    file_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredReport {
+    pub received_at: u64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: String,
+    pub body: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkReportSummary {
+    pub total_reports: u64,
+    /// Reports not stored because the file sink was full or failed.
+    pub dropped_reports: u64,
+    /// Counts per report type; types beyond the first 32 are counted as `other`.
+    pub by_type: BTreeMap<String, u64>,
+    /// Newest first; empty for the file sink.
+    pub recent: Vec<StoredReport>,
+}
+
+#[derive(Deserialize)]
+struct IncomingReport {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    body: serde_json::Value,
+}
+
+impl NetworkReportCollector {
+    pub fn in_memory(capacity: usize) -> Self {
+        Self::with_sink(ReportSink::Memory { capacity }, None, 0)
+    }
+
+    /// Appends to `path`, counting what is already there against `max_bytes`.
+    pub fn to_file(path: impl Into<PathBuf>, max_bytes: u64) -> Result<Self, SecurityConfigError> {
+        let path = path.into();
+        let open_error = |source| SecurityConfigError::ReportSink {
+            path: path.display().to_string(),
+            source,
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(open_error)?;
+        let file_bytes = file.metadata().map_err(open_error)?.len();
+        Ok(Self::with_sink(
+            ReportSink::File { path, max_bytes },
+            Some(file),
+            file_bytes,
+        ))
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    fn with_sink(sink: ReportSink, file: Option<std::fs::File>, file_bytes: u64) -> Self {
+        Self {
+            max_body_bytes: MAX_NETWORK_REPORT_BYTES,
+            inner: Arc::new(NetworkReportCollectorInner {
+                sink,
+                state: Mutex::new(NetworkReportState {
+                    file,
+                    file_bytes,
+                    ..Default::default()
+                }),
+            }),
+        }
+    }
+
+    /// `POST {path}` receives reports from browsers.
+    pub fn routes<S>(&self, path: &str) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Router::new()
+            .route(path, post(receive_network_reports))
+            .layer(DefaultBodyLimit::max(self.max_body_bytes))
+            .with_state(self.clone())
+    }
+
+    /// `GET {path}` returns [`NetworkReportSummary`]. Kept apart from
+    /// [`routes`](Self::routes) because report bodies describe visitors'
+    /// requests; mount it behind authentication.
+    pub fn summary_routes<S>(&self, path: &str) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Router::new()
+            .route(path, get(network_report_summary))
+            .with_state(self.clone())
+    }
+
+    pub fn summary(&self) -> NetworkReportSummary {
+        let state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        NetworkReportSummary {
+            total_reports: state.total,
+            dropped_reports: state.dropped,
+            by_type: state.by_type.clone(),
+            recent: state.recent.iter().rev().cloned().collect(),
+        }
+    }
+
+    fn record(&self, reports: Vec<StoredReport>) {
+        let mut state = self.inner.state.lock().unwrap_or_else(|e| e.into_inner());
+        for report in reports {
+            state.total += 1;
+            let kind = if state.by_type.contains_key(&report.kind)
+                || state.by_type.len() < MAX_REPORT_TYPES
+            {
+                report.kind.clone()
+            } else {
+                OTHER_REPORT_TYPE.to_string()
+            };
+            *state.by_type.entry(kind).or_default() += 1;
+
+            match &self.inner.sink {
+                ReportSink::Memory { capacity } => {
+                    if state.recent.len() >= *capacity {
+                        state.recent.pop_front();
+                    }
+                    if *capacity > 0 {
+                        state.recent.push_back(report);
+                    }
+                }
+                ReportSink::File { path, max_bytes } => {
+                    let Ok(mut line) = serde_json::to_vec(&report) else {
+                        state.dropped += 1;
+                        continue;
+                    };
+                    line.push(b'\n');
+                    if state.file_bytes + line.len() as u64 > *max_bytes {
+                        state.dropped += 1;
+                        continue;
+                    }
+                    // Single small appends; not worth a blocking task per report.
+                    let written = state
+                        .file
+                        .as_mut()
+                        .map(|file| std::io::Write::write_all(file, &line));
+                    match written {
+                        Some(Ok(())) => state.file_bytes += line.len() as u64,
+                        Some(Err(error)) => {
+                            tracing::warn!(path = %path.display(), %error, "failed to store report");
+                            state.dropped += 1;
+                        }
+                        None => state.dropped += 1,
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub async fn receive_network_reports(
+    State(collector): State<NetworkReportCollector>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if body.len() > collector.max_body_bytes {
+        return StatusCode::PAYLOAD_TOO_LARGE;
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if content_type != "application/reports+json" {
+        return StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    }
+
+    let Ok(reports) = serde_json::from_slice::<Vec<IncomingReport>>(&body) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let reports = reports
+        .into_iter()
+        .filter(|report| {
+            !report.kind.is_empty()
+                && report.kind.len() <= 64
+                && report
+                    .kind
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c == '-')
+        })
+        .map(|report| StoredReport {
+            received_at: now,
+            kind: report.kind,
+            url: normalize_report_uri(&report.url),
+            body: scrub_report_body(report.body),
+        })
+        .collect();
+    collector.record(reports);
+    StatusCode::NO_CONTENT
+}
+
+/// Normalizes URL-like strings anywhere in a report body, e.g. NEL `referrer`,
+/// and caps the length of the others.
+fn scrub_report_body(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        Value::String(text) if text.contains("://") => Value::String(normalize_report_uri(&text)),
+        Value::String(mut text) => {
+            let mut end = text.len().min(MAX_REPORT_FIELD_LEN);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            Value::String(text)
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(scrub_report_body).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key, scrub_report_body(value)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+pub async fn network_report_summary(
+    State(collector): State<NetworkReportCollector>,
+) -> Json<NetworkReportSummary> {
+    Json(collector.summary())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Sends `req` to `app`, which should end in a handler or fallback.
+    fn send(app: Router, req: Request<Body>) -> Response {
+        block_on(app.oneshot(req)).unwrap()
+    }
+
+    fn ok_router() -> Router {
+        Router::new().fallback(|| async { "ok" })
+    }
+
+    #[test]
+    fn csp_reports_are_validated_and_grouped() {
+        let collector = CspReportCollector::with_limits(1024, 2);
+        let app = Router::new()
+            .merge(collector.routes("/csp-report"))
+            .merge(collector.summary_routes("/admin/csp-report"));
+        let post = |content_type: &str, body: String| {
+            send(
+                app.clone(),
+                Request::post("/csp-report")
+                    .header(header::CONTENT_TYPE, content_type)
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .status()
+        };
+        let legacy = r#"{"csp-report": {"document-uri": "https://example.com/a?token=1",
+            "violated-directive": "script-src 'self'", "blocked-uri": "https://evil.example/x.js"}}"#;
+        let batch = r#"[{"type": "csp-violation", "body": {"documentURL": "https://example.com/b",
+            "effectiveDirective": "script-src-elem", "blockedURL": "inline"}},
+            {"type": "deprecation", "body": {}}]"#;
+
+        assert_eq!(
+            post("application/csp-report", legacy.into()),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            post("application/csp-report", legacy.into()),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            post("application/reports+json", batch.into()),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            post("application/csp-report", "{}".into()),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post("text/plain", legacy.into()),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            post("application/csp-report", "x".repeat(2048)),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let summary = collector.summary();
+        assert_eq!(summary.total_reports, 3);
+        assert_eq!(summary.groups[0].directive, "script-src");
+        assert_eq!(summary.groups[0].blocked_uri, "https://evil.example/x.js");
+        assert_eq!(summary.groups[0].count, 2);
+        assert_eq!(summary.groups[0].last_document_uri, "https://example.com/a");
+
+        // Past `max_groups`, new directive/URI pairs are only counted.
+        let third = legacy.replace("evil.example", "other.example");
+        assert_eq!(
+            post("application/csp-report", third),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(collector.summary().dropped_reports, 1);
+
+        let summary = send(
+            app.clone(),
+            Request::get("/admin/csp-report")
+                .body(Body::empty())
+                .unwrap(),
+        );
+        assert_eq!(summary.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn each_request_gets_a_fresh_csp_nonce() {
+        let config = SecurityHeadersConfig {
+            csp_nonce: true,
+            ..Default::default()
+        };
+        let app = Router::new()
+            .fallback(
+                |axum::Extension(nonce): axum::Extension<CspNonce>| async move {
+                    [("x-nonce", nonce.to_string())]
+                },
+            )
+            .layer(config.into_layer().unwrap());
+        let nonce_and_csp = || {
+            let response = send(app.clone(), Request::get("/").body(Body::empty()).unwrap());
+            let headers = response.headers();
+            (
+                headers["x-nonce"].to_str().unwrap().to_string(),
+                headers[header::CONTENT_SECURITY_POLICY]
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            )
+        };
+
+        let (nonce, csp) = nonce_and_csp();
+        assert_eq!(BASE64.decode(&nonce).unwrap().len(), 16);
+        let policy: ContentSecurityPolicy = csp.parse().unwrap();
+        for directive in [Directive::ScriptSrc, Directive::StyleSrc] {
+            assert!(policy
+                .sources(directive)
+                .unwrap()
+                .contains(&Source::nonce(nonce.as_str())));
+        }
+        assert_ne!(nonce_and_csp().0, nonce);
+
+        // A nonce would restrict a directive the policy leaves open.
+        let open: ContentSecurityPolicy = "img-src 'self'".parse().unwrap();
+        assert_eq!(open.with_nonce(&CspNonce::generate()), open);
+    }
+
+    #[test]
+    fn security_headers_can_be_enabled_disabled_or_overridden() {
+        let get = || Request::get("/").body(Body::empty()).unwrap();
+
+        let defaults = send(
+            ok_router().layer(SecurityHeadersConfig::default().into_layer().unwrap()),
+            get(),
+        );
+        let headers = defaults.headers();
+        assert!(headers[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .starts_with("default-src 'self'"));
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(headers[header::REFERRER_POLICY], DEFAULT_REFERRER_POLICY);
+
+        let config = SecurityHeadersConfig::from_toml_str(
+            r#"
+            [security_headers]
+            x_frame_options = false
+            referrer_policy = "no-referrer"
+            strict_transport_security = "max-age=600"
+            assume_https = true
+            "#,
+        )
+        .unwrap();
+        let customized = send(ok_router().layer(config.into_layer().unwrap()), get());
+        let headers = customized.headers();
+        assert!(!headers.contains_key(header::X_FRAME_OPTIONS));
+        assert_eq!(headers[header::REFERRER_POLICY], "no-referrer");
+        assert_eq!(headers[header::STRICT_TRANSPORT_SECURITY], "max-age=600");
+
+        let invalid = SecurityHeadersConfig {
+            referrer_policy: HeaderSetting::Value("no-referrer\u{7f}".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            invalid.into_layer(),
+            Err(SecurityConfigError::InvalidHeaderValue { .. })
+        ));
+    }
+
+    #[test]
+    fn private_network_preflights_are_answered_for_opted_in_origins() {
+        let preflight = |app: &Router, origin: &'static str| {
+            let response = send(
+                app.clone(),
+                Request::options("/devices")
+                    .header(header::ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                    .header("access-control-request-private-network", "true")
+                    .body(Body::empty())
+                    .unwrap(),
+            );
+            response
+                .headers()
+                .get("access-control-allow-private-network")
+                .cloned()
+        };
+        let config = CorsConfig {
+            allowed_origins: vec![
+                "https://app.example.com".into(),
+                "https://docs.example.com".into(),
+            ],
+            ..Default::default()
+        };
+
+        let off = ok_router().layer(config.into_layer().unwrap());
+        assert_eq!(preflight(&off, "https://app.example.com"), None);
+
+        let on = ok_router().layer(
+            CorsConfig {
+                private_network_origins: vec!["https://app.example.com".into()],
+                ..config.clone()
+            }
+            .into_layer()
+            .unwrap(),
+        );
+        assert_eq!(preflight(&on, "https://app.example.com").unwrap(), "true");
+        assert_eq!(preflight(&on, "https://docs.example.com"), None);
+
+        let wildcard = CorsConfig {
+            private_network_origins: vec!["*".into()],
+            ..config
+        };
+        assert!(wildcard
+            .policy_issues()
+            .contains(&CorsPolicyIssue::WildcardPrivateNetwork));
+    }
+
+    #[test]
+    fn rate_limit_and_request_id_headers_are_exposed_once() {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://app.example.com".into()],
+            expose_headers: vec!["X-Request-Id".into(), "etag".into()],
+            ..Default::default()
+        }
+        .with_rate_limit_headers()
+        .with_request_id_header("x-request-id");
+        let exposed = config.exposed_headers().unwrap();
+        assert_eq!(exposed.len(), 2 + RATE_LIMIT_HEADERS.len());
+        assert_eq!(
+            exposed
+                .iter()
+                .filter(|name| *name == "x-request-id")
+                .count(),
+            1
+        );
+
+        let response = send(
+            ok_router().layer(config.into_layer().unwrap()),
+            Request::get("/")
+                .header(header::ORIGIN, "https://app.example.com")
+                .body(Body::empty())
+                .unwrap(),
+        );
+        let expose = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(expose.contains("ratelimit-remaining"));
+        assert!(expose.contains("retry-after"));
+        assert!(expose.contains("x-request-id"));
+    }
+
+    #[test]
+    fn reloaded_origins_apply_to_existing_layers() {
+        let path = std::env::temp_dir().join(format!("cors-reload-{}.toml", std::process::id()));
+        let write_origins = |origins: &str| {
+            std::fs::write(&path, format!("[cors]\nallowed_origins = [{origins}]\n")).unwrap()
+        };
+        write_origins(r#""https://a.example.com""#);
+        let origins = ReloadableOrigins::from_toml_file(&path).unwrap();
+        let app = ok_router().layer(origins.layer().unwrap());
+        let allowed_origin = |origin: &'static str| {
+            let response = send(
+                app.clone(),
+                Request::get("/")
+                    .header(header::ORIGIN, origin)
+                    .body(Body::empty())
+                    .unwrap(),
+            );
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .cloned()
+        };
+        assert!(allowed_origin("https://b.example.com").is_none());
+
+        write_origins(r#""https://b.example.com""#);
+        let diff = origins.reload().unwrap();
+        assert_eq!(diff.added, ["https://b.example.com"]);
+        assert_eq!(diff.removed, ["https://a.example.com"]);
+        assert!(allowed_origin("https://b.example.com").is_some());
+        assert!(allowed_origin("https://a.example.com").is_none());
+
+        // A bad file leaves the previous list in place.
+        write_origins(r#""ftp://c.example.com""#);
+        assert!(origins.reload().is_err());
+        assert_eq!(origins.origins(), ["https://b.example.com"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cors_matches_subdomain_wildcards_and_patterns() {
+        let config = CorsConfig {
+            allowed_origins: vec![
+                "https://*.app.example.com".into(),
+                r"regex:https://tenant-[a-z0-9]+\.example\.com".into(),
+            ],
+            ..Default::default()
+        };
+        let matchers = config.origins().unwrap();
+        let allowed = |origin: &'static str| {
+            let origin = HeaderValue::from_static(origin);
+            matchers.iter().any(|matcher| matcher.matches(&origin))
+        };
+        assert!(allowed("https://a.app.example.com"));
+        assert!(allowed("https://A.App.Example.com"));
+        assert!(allowed("https://tenant-7.example.com"));
+        assert!(!allowed("https://evilapp.example.com"));
+        assert!(!allowed("https://app.example.com"));
+        assert!(!allowed("http://a.app.example.com"));
+        assert!(!allowed("https://a.app.example.com:8443"));
+        assert!(!allowed("https://tenant-7.example.com.attacker.invalid"));
+
+        let response = send(
+            ok_router().layer(config.into_layer().unwrap()),
+            Request::get("/")
+                .header(header::ORIGIN, "https://a.app.example.com")
+                .body(Body::empty())
+                .unwrap(),
+        );
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://a.app.example.com"
+        );
+        assert!(headers
+            .get_all(header::VARY)
+            .iter()
+            .any(|value| value.to_str().unwrap().contains("origin")));
+
+        let top_level = CorsConfig {
+            allowed_origins: vec!["https://*.com".into()],
+            ..Default::default()
+        };
+        assert!(top_level
+            .policy_issues()
+            .contains(&CorsPolicyIssue::TopLevelSubdomainWildcard));
+    }
+
+    #[test]
+    fn cors_exposes_rate_limit_and_request_id_headers_from_env() {
+        std::env::set_var("ALLOWED_ORIGINS", "https://app.example.com");
+        std::env::set_var("CORS_EXPOSE_RATE_LIMIT_HEADERS", "true");
+        std::env::set_var("CORS_EXPOSE_REQUEST_ID_HEADER", "x-request-id");
+        let config = CorsConfig::from_env();
+        std::env::set_var("CORS_EXPOSE_RATE_LIMIT_HEADERS", "yes");
+        let invalid = CorsConfig::from_env();
+        for var in [
+            "ALLOWED_ORIGINS",
+            "CORS_EXPOSE_RATE_LIMIT_HEADERS",
+            "CORS_EXPOSE_REQUEST_ID_HEADER",
+        ] {
+            std::env::remove_var(var);
+        }
+
+        let exposed = config.unwrap().exposed_headers().unwrap();
+        assert!(exposed.contains(&HeaderName::from_static("retry-after")));
+        assert!(exposed.contains(&HeaderName::from_static("x-request-id")));
+        assert!(matches!(
+            invalid,
+            Err(SecurityConfigError::InvalidEnvVar {
+                var: "CORS_EXPOSE_RATE_LIMIT_HEADERS",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn trailing_slashes_do_not_change_route_precedence() {
+        let json_api = |routes: &[&str]| SecurityHeaderProfileConfig {
+            routes: routes.iter().map(|route| route.to_string()).collect(),
+            content_types: Vec::new(),
+            ..SecurityHeaderProfileConfig::json_api()
+        };
+        let layer = SecurityHeaderProfiles::new(SecurityHeadersConfig::default())
+            .with_profile("a", json_api(&["/api"]))
+            .with_profile("b", json_api(&["/api/"]))
+            .into_layer()
+            .unwrap();
+        let cors = CorsProfiles::new(CorsConfig {
+            allowed_origins: vec!["https://app.example.com".into()],
+            ..Default::default()
+        });
+        let cors = cors
+            .clone()
+            .with_profile("a", ["/api"], cors.fallback.clone())
+            .with_profile("b", ["/api/"], cors.fallback.clone());
+
+        let headers_profile = layer.for_route("/api/users").unwrap();
+        assert!(Arc::ptr_eq(&headers_profile, &layer.profiles[0].headers));
+        let routes = cors.indexed_routes();
+        assert_eq!(route_index(&routes, "/api/users"), Some(0));
+        assert!(std::ptr::eq(
+            cors.for_path("/api/users"),
+            &cors.profiles["a"].cors
+        ));
+    }
+
+    #[test]
+    fn cors_requires_origins_and_profiles_inherit_the_fallback() {
+        assert!(matches!(
+            CorsConfig::default().validate(),
+            Err(SecurityConfigError::NoAllowedOrigins)
+        ));
+        assert!(matches!(
+            CorsConfig::from_toml_str("[cors]\nallow_credentials = false"),
+            Err(SecurityConfigError::NoAllowedOrigins)
+        ));
+
+        let profiles = CorsProfiles::from_toml_str(
+            r#"
+            [cors]
+            allowed_origins = ["https://app.example.com"]
+            max_age_secs = 600
+
+            [cors_profiles.uploads]
+            routes = ["/uploads"]
+            allowed_methods = ["PUT"]
+            "#,
+        )
+        .unwrap();
+        let uploads = profiles.for_path("/uploads/1");
+        assert_eq!(uploads.allowed_origins, ["https://app.example.com"]);
+        assert_eq!(uploads.allowed_methods, ["PUT"]);
+        assert_eq!(uploads.max_age_secs, Some(600));
+    }
+
+    fn cross_origin_parts(path: &str, origin: &str) -> Parts {
+        Request::get(path)
+            .header(header::HOST, "api.example.com")
+            .header(header::ORIGIN, origin)
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    #[test]
+    fn cors_monitor_counts_rejections() {
+        let monitor = CorsMonitor::new(&CorsConfig {
+            allowed_origins: vec!["https://app.example.com".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        let preflight = |method: &str, headers: &str| {
+            let mut parts = cross_origin_parts("/", "https://app.example.com");
+            parts.method = Method::OPTIONS;
+            parts.headers.insert(
+                header::ACCESS_CONTROL_REQUEST_METHOD,
+                HeaderValue::from_str(method).unwrap(),
+            );
+            parts.headers.insert(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                HeaderValue::from_str(headers).unwrap(),
+            );
+            parts
+        };
+
+        monitor.inspect(&preflight("GET", "content-type"));
+        monitor.inspect(&preflight("PATCH", "content-type"));
+        monitor.inspect(&preflight("GET", "content-type, x-debug"));
+        monitor.inspect(&cross_origin_parts("/", "https://api.example.com"));
+        for _ in 0..3 {
+            monitor.inspect(&cross_origin_parts("/", "https://unknown.example"));
+        }
+
+        let metrics = monitor.metrics();
+        assert_eq!(metrics.rejected_methods, 1);
+        assert_eq!(metrics.rejected_headers, 1);
+        // The same-origin request is not counted.
+        assert_eq!(metrics.rejected_origins, 3);
+        assert_eq!(
+            metrics.unknown_origins,
+            BTreeMap::from([("https://unknown.example".to_string(), 3)])
+        );
+
+        for i in 0..MAX_TRACKED_ORIGINS + 10 {
+            monitor.record_unknown_origin(&format!("https://{i}.example"));
+        }
+        assert_eq!(monitor.metrics().unknown_origins.len(), MAX_TRACKED_ORIGINS);
+    }
+
+    #[test]
+    fn cors_monitor_follows_reloads_and_profiles() {
+        let path = std::env::temp_dir().join(format!("cors-monitor-{}.toml", std::process::id()));
+        let write_origins = |origins: &str| {
+            std::fs::write(&path, format!("[cors]\nallowed_origins = [{origins}]\n")).unwrap()
+        };
+        write_origins(r#""https://app.example.com""#);
+        let origins = ReloadableOrigins::from_toml_file(&path).unwrap();
+        let monitor = CorsMonitor::for_reloadable(&origins).unwrap();
+
+        monitor.inspect(&cross_origin_parts("/", "https://admin.example.com"));
+        assert_eq!(monitor.metrics().rejected_origins, 1);
+        write_origins(r#""https://app.example.com", "https://admin.example.com""#);
+        origins.reload().unwrap();
+        monitor.inspect(&cross_origin_parts("/", "https://admin.example.com"));
+        assert_eq!(monitor.metrics().rejected_origins, 1);
+        std::fs::remove_file(&path).unwrap();
+
+        let profiles = CorsProfiles::new(CorsConfig {
+            allowed_origins: vec!["https://app.example.com".into()],
+            ..Default::default()
+        })
+        .with_profile(
+            "public",
+            ["/public/"],
+            CorsConfig {
+                allowed_origins: vec!["*".into()],
+                allow_credentials: false,
+                ..Default::default()
+            },
+        );
+        let monitor = CorsMonitor::for_profiles(&profiles).unwrap();
+        monitor.inspect(&cross_origin_parts("/public/feed", "https://other.example"));
+        assert_eq!(monitor.metrics().rejected_origins, 0);
+        monitor.inspect(&cross_origin_parts("/private", "https://other.example"));
+        assert_eq!(monitor.metrics().rejected_origins, 1);
+    }
+
+    #[test]
+    fn unknown_csp_directives_round_trip() {
+        let value = "default-src 'self'; block-all-mixed-content; \
+                     fenced-frame-src https://ads.example.com; webrtc 'block'";
+        let policy: ContentSecurityPolicy = value.parse().unwrap();
+        assert_eq!(policy.to_string(), value);
+        assert_eq!(
+            policy.to_string().parse::<ContentSecurityPolicy>().unwrap(),
+            policy
+        );
+        assert!("webrtc 'allow'; webrtc 'block'"
+            .parse::<ContentSecurityPolicy>()
+            .is_err());
+
+        let config = SecurityHeadersConfig {
+            content_security_policy: HeaderSetting::Value(value.into()),
+            ..Default::default()
+        };
+        assert!(config.into_layer().is_ok());
+    }
+
+    #[test]
+    fn csp_parses_diffs_and_merges() {
+        let base: ContentSecurityPolicy =
+            "default-src 'self'; img-src 'self' https:; frame-ancestors 'none'"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            base.to_string(),
+            "default-src 'self'; img-src 'self' https:; frame-ancestors 'none'"
+        );
+        assert_eq!(
+            ContentSecurityPolicy::new()
+                .directive(Directive::DefaultSrc, [Source::SelfOrigin])
+                .directive(
+                    Directive::ImgSrc,
+                    [Source::SelfOrigin, Source::scheme("https")]
+                )
+                .directive(Directive::FrameAncestors, [Source::None]),
+            base
+        );
+
+        let cdn: ContentSecurityPolicy = "script-src cdn.example.com 'strict-dynamic'"
+            .parse()
+            .unwrap();
+        let mut merged = base.clone();
+        merged.merge(&cdn);
+        assert_eq!(
+            merged.sources(Directive::ScriptSrc).unwrap(),
+            [
+                Source::SelfOrigin,
+                Source::host("cdn.example.com"),
+                Source::StrictDynamic,
+            ]
+        );
+        let diff = base.diff(&merged);
+        assert!(diff.removed.is_empty());
+        assert!(diff
+            .added
+            .contains(&("script-src".into(), "'strict-dynamic'".into())));
+
+        for invalid in [
+            "default-src 'slef'",
+            "default-src 'none' 'self'",
+            "script-src a; script-src b",
+        ] {
+            assert!(
+                invalid.parse::<ContentSecurityPolicy>().is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn inline_scripts_and_styles_are_allowed_by_hash() {
+        const SCRIPT_HASH: &str = "sha256-bhHHL3z2vDgxUt0W3dWQOrprscmda2Y5pLsLg4GF+pI=";
+        assert_eq!(
+            Source::sha256_of("alert(1)").to_string(),
+            format!("'{SCRIPT_HASH}'")
+        );
+
+        let policy: ContentSecurityPolicy = "default-src 'self'; style-src 'self' 'unsafe-inline'"
+            .parse()
+            .unwrap();
+        let policy = policy
+            .allow_inline_script("alert(1)")
+            .allow_inline_style("body{}");
+        assert_eq!(
+            policy.sources(Directive::ScriptSrc).unwrap(),
+            [Source::SelfOrigin, Source::sha256_of("alert(1)")]
+        );
+        assert!(!policy
+            .sources(Directive::StyleSrc)
+            .unwrap()
+            .contains(&Source::UnsafeInline));
+
+        let config = SecurityHeadersConfig {
+            inline_style_hashes: vec![SCRIPT_HASH.into()],
+            ..Default::default()
+        }
+        .with_inline_script("alert(1)");
+        let csp = config.csp().unwrap().unwrap();
+        assert!(csp
+            .sources(Directive::ScriptSrc)
+            .unwrap()
+            .contains(&Source::sha256_of("alert(1)")));
+        assert!(csp
+            .sources(Directive::StyleSrc)
+            .unwrap()
+            .contains(&Source::sha256_of("alert(1)")));
+
+        let path = std::env::temp_dir().join(format!("inline-{}.js", std::process::id()));
+        std::fs::write(&path, "alert(1)").unwrap();
+        let from_file = SecurityHeadersConfig {
+            inline_script_files: vec![path.clone()],
+            ..Default::default()
+        }
+        .csp();
+        std::fs::remove_file(&path).unwrap();
+        assert!(from_file
+            .unwrap()
+            .unwrap()
+            .sources(Directive::ScriptSrc)
+            .unwrap()
+            .contains(&Source::sha256_of("alert(1)")));
+
+        let not_a_hash = SecurityHeadersConfig {
+            inline_script_hashes: vec!["self".into()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            not_a_hash.validate(),
+            Err(SecurityConfigError::InvalidCsp { .. })
+        ));
+    }
+
+    #[test]
+    fn worker_src_inherits_script_src() {
+        let policy: ContentSecurityPolicy =
+            "default-src 'none'; script-src 'self'".parse().unwrap();
+        assert_eq!(
+            policy.effective_sources(Directive::WorkerSrc),
+            Some([Source::SelfOrigin].as_slice())
+        );
+        assert_eq!(
+            policy.effective_sources(Directive::FrameSrc),
+            Some([Source::None].as_slice())
+        );
+
+        let policy = policy.add_source(Directive::WorkerSrc, Source::scheme("blob"));
+        assert_eq!(
+            policy.to_string(),
+            "default-src 'none'; script-src 'self'; worker-src 'self' blob:"
+        );
+    }
+
+    #[test]
+    fn origin_patterns_matching_lookalike_hosts_are_rejected() {
+        let issues = |pattern: &str| {
+            CorsConfig {
+                allowed_origins: vec![format!("regex:{pattern}")],
+                allow_credentials: true,
+                ..Default::default()
+            }
+            .policy_issues()
+        };
+        let permissive = CorsPolicyIssue::PermissiveOriginPatternWithCredentials;
+
+        // Suffix without a dot boundary: matches https://evilexample.com.
+        assert!(issues(r"https://.*example\.com").contains(&permissive));
+        // Unescaped dots: matches https://appxexample.com.
+        assert!(issues("https://app.example.com").contains(&permissive));
+        // Unanchored suffix: matches https://app.example.com.attacker.invalid.
+        assert!(issues(r"https://app\.example\.com.*").contains(&permissive));
+
+        assert!(issues(r"https://.*\.example\.com").is_empty());
+        assert!(issues(r"https://(app|admin)\.example\.com").is_empty());
+    }
+
+    #[test]
+    fn cross_origin_defaults_allow_popups_and_same_site_embeds() {
+        let header = |config: &SecurityHeadersConfig, name: &str| {
+            config
+                .cross_origin_headers()
+                .unwrap()
+                .into_iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value)
+        };
+
+        let config = SecurityHeadersConfig::default();
+        assert_eq!(
+            header(&config, "cross-origin-opener-policy").unwrap(),
+            "same-origin-allow-popups"
+        );
+        assert_eq!(
+            header(&config, "cross-origin-resource-policy").unwrap(),
+            "same-site"
+        );
+        assert!(header(&config, "cross-origin-embedder-policy").is_none());
+
+        let isolated = SecurityHeadersConfig::default().cross_origin_isolated();
+        assert_eq!(
+            header(&isolated, "cross-origin-opener-policy").unwrap(),
+            "same-origin"
+        );
+        assert_eq!(
+            header(&isolated, "cross-origin-resource-policy").unwrap(),
+            "same-origin"
+        );
+        assert_eq!(
+            header(&isolated, "cross-origin-embedder-policy").unwrap(),
+            "require-corp"
+        );
+    }
+
+    #[test]
+    fn permissive_origin_patterns_are_rejected() {
+        let with_credentials = |origin: &str| CorsConfig {
+            allowed_origins: vec![origin.to_string()],
+            allow_credentials: true,
+            ..Default::default()
+        };
+
+        for pattern in ["regex:.*", "regex:https?://.*"] {
+            assert!(with_credentials(pattern)
+                .policy_issues()
+                .contains(&CorsPolicyIssue::PermissiveOriginPatternWithCredentials));
+        }
+        assert!(with_credentials("regex:null")
+            .policy_issues()
+            .contains(&CorsPolicyIssue::NullOrigin));
+        assert!(with_credentials(r"regex:https://[a-z0-9-]+\.example\.com")
+            .policy_issues()
+            .is_empty());
+    }
+
+    #[test]
+    fn network_reports_are_bounded_and_scrubbed() {
+        let collector = NetworkReportCollector::in_memory(4);
+        let reports = (0..MAX_REPORT_TYPES + 8)
+            .map(|i| StoredReport {
+                received_at: 0,
+                kind: format!("type-{i}"),
+                url: String::new(),
+                body: serde_json::Value::Null,
+            })
+            .collect();
+        collector.record(reports);
+
+        let summary = collector.summary();
+        assert_eq!(summary.by_type.len(), MAX_REPORT_TYPES + 1);
+        assert_eq!(summary.by_type[OTHER_REPORT_TYPE], 8);
+        assert_eq!(summary.recent.len(), 4);
+
+        let body = scrub_report_body(serde_json::json!({
+            "referrer": "https://example.com/reset?token=secret",
+            "phase": "connection",
+        }));
+        assert_eq!(body["referrer"], "https://example.com/reset");
+        assert_eq!(body["phase"], "connection");
+    }
+
+    #[test]
+    fn report_only_csp_without_other_headers() {
+        let config = SecurityHeadersConfig::from_toml_str(
+            r#"
+            [security_headers]
+            content_security_policy = false
+            x_content_type_options = false
+            x_frame_options = false
+            referrer_policy = false
+            permissions_policy = false
+            cross_origin_opener_policy = false
+            cross_origin_resource_policy = false
+            content_security_policy_report_only = "default-src 'self'"
+            "#,
+        )
+        .unwrap();
+
+        let layer = config.into_layer().unwrap();
+        let names: Vec<_> = layer.default.headers.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, [header::CONTENT_SECURITY_POLICY_REPORT_ONLY]);
+    }
+
+    fn hsts_for(config: &SecurityHeadersConfig, req: &Request<()>) -> Option<HeaderValue> {
+        let layer = config.into_layer().unwrap();
+        let context = RequestContext {
+            transport: Transport::from_request(req),
+            ..Default::default()
+        };
+        let mut response = Response::new(());
+        layer.apply(&context, &mut response);
+        response
+            .headers()
+            .get(header::STRICT_TRANSPORT_SECURITY)
+            .cloned()
+    }
+
+    #[test]
+    fn hsts_on_origin_form_tls_requests() {
+        let config = SecurityHeadersConfig::default();
+        let plain = Request::get("/").body(()).unwrap();
+        assert_eq!(hsts_for(&config, &plain), None);
+
+        let mut tls = Request::get("/").body(()).unwrap();
+        tls.extensions_mut().insert(TlsConnection);
+        assert!(hsts_for(&config, &tls).is_some());
+
+        let assume_https = SecurityHeadersConfig {
+            assume_https: true,
+            ..Default::default()
+        };
+        assert!(hsts_for(&assume_https, &plain).is_some());
+    }
+
+    #[test]
+    fn no_redirect_on_tls_connections() {
+        let config = HttpsRedirectConfig::new(["example.com"]);
+        let mut req = Request::get("/x")
+            .header(header::HOST, "example.com")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            config.redirect_target(&req),
+            Ok(Some("https://example.com/x".to_string()))
+        );
+
+        req.extensions_mut().insert(TlsConnection);
+        assert_eq!(config.redirect_target(&req), Ok(None));
+    }
+
+    #[test]
+    fn absolute_https_targets_on_plain_http_are_redirected() {
+        let req = Request::get("https://example.com/x")
+            .header(header::HOST, "example.com")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            HttpsRedirectConfig::new(["example.com"]).redirect_target(&req),
+            Ok(Some("https://example.com/x".to_string()))
+        );
+        assert_eq!(hsts_for(&SecurityHeadersConfig::default(), &req), None);
+    }
+
+    #[test]
+    fn profiles_keep_no_store_for_authenticated_requests() {
+        let layer = SecurityHeaderProfiles::recommended(SecurityHeadersConfig {
+            no_store_authenticated: true,
+            ..Default::default()
+        })
+        .into_layer()
+        .unwrap();
+        let req = Request::get("/api")
+            .header(header::AUTHORIZATION, "Bearer token")
+            .body(())
+            .unwrap();
+        let context = RequestContext {
+            transport: Transport::from_request(&req),
+            ..Default::default()
+        };
+        let mut response = Response::new(());
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        layer.apply(&context, &mut response);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[test]
+    fn profiles_inherit_request_settings() {
+        let layer = SecurityHeaderProfiles::recommended(SecurityHeadersConfig {
+            assume_https: true,
+            strip_headers: Some(vec!["x-internal".into()]),
+            ..Default::default()
+        })
+        .into_layer()
+        .unwrap();
+        let mut response = Response::new(());
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        headers.insert("x-internal", HeaderValue::from_static("1"));
+        layer.apply(&RequestContext::default(), &mut response);
+
+        assert!(response
+            .headers()
+            .contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert!(!response.headers().contains_key("x-internal"));
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY],
+            "default-src 'none'; style-src 'unsafe-inline'; sandbox"
+        );
+    }
+}